@@ -1,7 +1,7 @@
 use std::{convert::Infallible, time::Duration};
 
-use bytes::Bytes;
 use maw::prelude::*;
+use tokio_util::sync::CancellationToken;
 
 #[tokio::main]
 async fn main() -> Result<(), MawError> {
@@ -17,28 +17,56 @@ async fn main() -> Result<(), MawError> {
 }
 
 pub async fn sse_handler(c: &mut Ctx) {
+    // Continue counting from the last event the browser got before reconnecting
+    let start = c
+        .req
+        .last_event_id()
+        .and_then(|id| id.parse().ok())
+        .unwrap_or(0_u64);
+
     c.res.sse(futures_util::stream::unfold(
-        (tokio::time::interval(Duration::from_secs(1)), 0_u64),
+        (tokio::time::interval(Duration::from_secs(1)), start),
         |(mut interval, count)| async move {
             interval.tick().await;
             let next = count + 1;
-            let msg = format!("event: tick\ndata: {{\"count\": {next}}}\n\n");
-            Some((Ok::<Bytes, Infallible>(Bytes::from(msg)), (interval, next)))
+            let event = Event::new()
+                .id(next.to_string())
+                .event("tick")
+                .json_data(&serde_json::json!({ "count": next }));
+            Some((event, (interval, next)))
         },
     ));
 }
 
 pub async fn sse_handler_2(c: &mut Ctx) {
-    c.res.sse(async_stream::stream! {
+    // Cancelled when the client disconnects, stopping the producer
+    let token = CancellationToken::new();
+    let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+
+    let producer = token.clone();
+    tokio::spawn(async move {
         let mut interval = tokio::time::interval(Duration::from_secs(1));
         let mut count = 0_u64;
-
-        loop {
+        while !producer.is_cancelled() {
             interval.tick().await;
             count += 1;
-
-            let msg = format!("event: tick\ndata: {{\"count\": {count}}}\n\n");
-            yield Ok::<Bytes, Infallible>(Bytes::from(msg));
+            let event = Event::new().event("tick").data(format!("count: {count}"));
+            if tx.send(event).await.is_err() {
+                break;
+            }
         }
     });
+
+    let stream = async_stream::stream! {
+        while let Some(event) = rx.recv().await {
+            yield Ok::<Event, Infallible>(event);
+        }
+    };
+
+    c.res.sse_with(
+        stream,
+        SseOptions::new()
+            .keep_alive(Some(Duration::from_secs(5)))
+            .cancellation(token),
+    );
 }
@@ -1,12 +1,15 @@
 use std::{
     collections::HashSet,
+    future::Future,
     net,
+    pin::Pin,
     sync::{Arc, RwLock},
 };
 
 use http::StatusCode;
-use hyper::{Request as HyperRequest, body::Incoming as IncomingBody};
+use hyper::Request as HyperRequest;
 use hyper_util::rt::{TokioExecutor, TokioIo};
+use ipnet::IpNet;
 use smol_str::SmolStr;
 use tokio::net::TcpListener;
 use tokio_util::sync::CancellationToken;
@@ -16,17 +19,28 @@ mod jinja;
 #[cfg(feature = "minijinja")]
 pub use jinja::Jinja;
 
+mod proxy_protocol;
+mod signal;
+pub use signal::Signal;
+
 use crate::{
     ALL,
     any_map::{AnyMap, SerializableAny},
     error::Error,
+    ip,
     request::Request,
-    response::{HttpBody, Response},
+    response::{BoxError, HttpBody, Response},
     router::{self, MatchRouter},
+    service::AppService,
 };
 
 type HttpResponse = http::Response<HttpBody>;
 
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+type LifecycleHook = Arc<dyn Fn(Arc<App>) -> BoxFuture<Result<(), BoxError>> + Send + Sync>;
+type BackgroundTask = Arc<dyn Fn(CancellationToken) -> BoxFuture<()> + Send + Sync>;
+type ReloadHook = Arc<dyn Fn(Arc<App>) -> BoxFuture<()> + Send + Sync>;
+
 pub struct App<S = ()> {
     pub state: Arc<S>,
     pub(crate) router: router::Router,
@@ -41,12 +55,20 @@ pub struct App<S = ()> {
     ///
     /// Default: 4MB
     pub(crate) body_limit: usize,
-    /// When set, `c.req.ip()` returns the value of the header returned by this function
-    /// instead of the remote TCP address. Useful when behind a reverse proxy or load
+    /// When set, `c.req.ip()` returns the address read from the header returned by this
+    /// function instead of the remote TCP address. Useful when behind a reverse proxy or load
     /// balancer (e.g. `X-Forwarded-For`, `CF-Connecting-IP`).
     ///
-    /// NOTE: headers are easily spoofed; never trust them for security-sensitive decisions.
+    /// NOTE: headers are easily spoofed, only `trusted_proxies` should be believed.
     pub(crate) proxy_header_fn: Option<Arc<dyn Fn() -> Option<String> + Send + Sync>>,
+    /// Peers whose proxy header is believed, see [`App::trusted_proxies`].
+    pub(crate) trusted_proxies: Vec<IpNet>,
+    proxy_protocol: bool,
+    startup_hooks: Vec<LifecycleHook>,
+    shutdown_hooks: Vec<LifecycleHook>,
+    background_tasks: Vec<BackgroundTask>,
+    shutdown_signals: Vec<Signal>,
+    reload_hook: Option<ReloadHook>,
 }
 
 impl Default for App {
@@ -69,6 +91,16 @@ impl App {
             dump_routes: false,
             body_limit: 4 * 1024 * 1024,
             proxy_header_fn: None,
+            trusted_proxies: Vec::new(),
+            proxy_protocol: false,
+            startup_hooks: Vec::new(),
+            shutdown_hooks: Vec::new(),
+            background_tasks: Vec::new(),
+            #[cfg(unix)]
+            shutdown_signals: vec![Signal::Interrupt, Signal::Terminate],
+            #[cfg(not(unix))]
+            shutdown_signals: vec![Signal::Interrupt],
+            reload_hook: None,
         }
     }
 
@@ -85,6 +117,13 @@ impl App {
             dump_routes: self.dump_routes,
             body_limit: self.body_limit,
             proxy_header_fn: self.proxy_header_fn,
+            trusted_proxies: self.trusted_proxies,
+            proxy_protocol: self.proxy_protocol,
+            startup_hooks: self.startup_hooks,
+            shutdown_hooks: self.shutdown_hooks,
+            background_tasks: self.background_tasks,
+            shutdown_signals: self.shutdown_signals,
+            reload_hook: self.reload_hook,
         }
     }
 
@@ -96,10 +135,10 @@ impl App {
         self
     }
 
-    /// ProxyHeader will enable c.req.ip() to return the value of the given header key
+    /// ProxyHeader will enable c.req.ip() to return the address in the given header key
     /// By default c.req.ip() will return the Remote IP from the TCP connection
-    /// This property can be useful if you are behind a load balancer: X-Forwarded-*
-    /// NOTE: headers are easily spoofed and the detected IP addresses are unreliable.
+    /// This property can be useful if you are behind a load balancer: X-Forwarded-For, Forwarded
+    /// NOTE: headers are easily spoofed, set `trusted_proxies` so only your proxies are believed.
     ///
     /// Default: None (disabled)
     pub fn proxy_header(self, header: impl Into<String>) -> Self {
@@ -123,6 +162,38 @@ impl App {
         self
     }
 
+    /// Only believe the proxy header when the peer is in one of these ranges, e.g.
+    /// `["10.0.0.0/8", "127.0.0.1"]`. The client is then the right-most address in the
+    /// header that isn't a trusted proxy.
+    ///
+    /// Without a `proxy_header`, `Forwarded` is read when present and `X-Forwarded-For` otherwise.
+    ///
+    /// Default: none, the proxy header is believed from any peer
+    #[track_caller]
+    pub fn trusted_proxies<I>(mut self, proxies: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.trusted_proxies = proxies
+            .into_iter()
+            .map(|p| ip::parse_net(p.as_ref()))
+            .collect();
+        self
+    }
+
+    /// Expect a PROXY protocol (v1 or v2) header at the start of every connection, as sent
+    /// by HAProxy or AWS NLB in TCP mode, and use the client address in it as the peer address.
+    ///
+    /// Connections without a valid header are closed, so only enable this when every
+    /// connection comes through such a proxy.
+    ///
+    /// Default: false
+    pub fn proxy_protocol(mut self, enabled: bool) -> Self {
+        self.proxy_protocol = enabled;
+        self
+    }
+
     /// Sets the router for the application.
     ///
     /// Changes to the router after the server has started will not take effect.
@@ -150,6 +221,75 @@ impl App {
         self
     }
 
+    /// Registers a hook that runs once before the server starts accepting connections.
+    ///
+    /// Hooks run in registration order. If one fails, startup is aborted and
+    /// `listen` returns the error.
+    pub fn on_startup<F, Fut, E>(mut self, f: F) -> Self
+    where
+        F: Fn(Arc<App>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Into<BoxError>,
+    {
+        self.startup_hooks.push(Arc::new(move |app| {
+            let fut = f(app);
+            Box::pin(async move { fut.await.map_err(Into::into) })
+        }));
+        self
+    }
+
+    /// Registers a hook that runs once after the shutdown token is cancelled and
+    /// all connections and background tasks are done, or the shutdown timeout elapsed.
+    /// Also runs when the server fails to start after the startup hooks succeeded.
+    ///
+    /// Hooks run in registration order and get their own shutdown timeout, after the one
+    /// for connections and background tasks. Errors are logged.
+    pub fn on_shutdown<F, Fut, E>(mut self, f: F) -> Self
+    where
+        F: Fn(Arc<App>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Into<BoxError>,
+    {
+        self.shutdown_hooks.push(Arc::new(move |app| {
+            let fut = f(app);
+            Box::pin(async move { fut.await.map_err(Into::into) })
+        }));
+        self
+    }
+
+    /// Spawns a task when the server starts, after the startup hooks.
+    ///
+    /// The task receives the shutdown token and should return once it is cancelled,
+    /// it is awaited during graceful shutdown.
+    pub fn spawn_background<F, Fut>(mut self, task: F) -> Self
+    where
+        F: Fn(CancellationToken) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.background_tasks
+            .push(Arc::new(move |token| Box::pin(task(token))));
+        self
+    }
+
+    /// Sets the signals that trigger a graceful shutdown when using [`App::listen`].
+    ///
+    /// Default: `[Signal::Interrupt, Signal::Terminate]` on unix, `[Signal::Interrupt]` elsewhere
+    pub fn shutdown_signals(mut self, signals: impl IntoIterator<Item = Signal>) -> Self {
+        self.shutdown_signals = signals.into_iter().collect();
+        self
+    }
+
+    /// Registers a callback that runs every time the process receives SIGHUP,
+    /// e.g. to re-read templates or TLS certificates. (unix only)
+    pub fn on_reload<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(Arc<App>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.reload_hook = Some(Arc::new(move |app| Box::pin(f(app))));
+        self
+    }
+
     /// Returns a clone of the server shutdown token.
     ///
     /// Long-lived tasks like SSE streams can use this to stop immediately
@@ -204,60 +344,75 @@ impl App {
 }
 
 impl App {
-    /// Listen with ctrl+c shutdown
+    /// Listen with shutdown on SIGINT/SIGTERM, see [`App::shutdown_signals`].
     pub async fn listen<A>(self, addr: A) -> Result<(), Error>
     where
         A: net::ToSocketAddrs + std::fmt::Debug + 'static,
     {
         let token = CancellationToken::new();
-        let t = token.clone();
-        tokio::spawn(async move {
-            tokio::signal::ctrl_c()
-                .await
-                .expect("failed to install CTRL+C signal handler");
-            t.cancel();
-        });
+        signal::cancel_on(&self.shutdown_signals, &token)?;
         self.listen_shutdown(addr, token).await
     }
 
     /// Listen with custom shutdown signal
-    pub async fn listen_shutdown<A>(
-        mut self,
-        addr: A,
-        shutdown: CancellationToken,
-    ) -> Result<(), Error>
+    pub async fn listen_shutdown<A>(self, addr: A, shutdown: CancellationToken) -> Result<(), Error>
     where
         A: net::ToSocketAddrs + std::fmt::Debug + 'static,
     {
-        if self.dump_routes {
-            tracing::info!("App Router: {:#?}", self.router);
+        let arc_app = self.prepare(shutdown.clone())?;
+
+        for hook in &arc_app.startup_hooks {
+            hook(arc_app.clone()).await.map_err(Error::Startup)?;
         }
 
-        self.built_router = self.router.build()?;
+        #[cfg(unix)]
+        if let Some(hook) = arc_app.reload_hook.clone() {
+            let mut hangup =
+                tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+            let app = arc_app.clone();
+            let shutdown = shutdown.clone();
+            tokio::spawn(async move {
+                while let Some(Some(())) = shutdown.run_until_cancelled(hangup.recv()).await {
+                    tracing::info!("Reload signal received!");
+                    hook(app.clone()).await;
+                }
+            });
+        }
 
-        self.shutdown = shutdown.clone();
+        // Cleanup in the shutdown hooks is owed once the startup hooks ran, even if
+        // binding fails or draining times out
+        let served = Self::serve(&arc_app, addr, &shutdown).await;
+        if served.is_err() {
+            shutdown.cancel();
+        }
 
-        let middlewares: Vec<_> = {
-            let mut called = HashSet::new();
-            self.router
-                .flatten_routers()
-                .iter()
-                .flat_map(|(_, m)| m.values())
-                .flat_map(|h| h.iter())
-                .filter(|h| called.insert(h.type_id()))
-                .cloned()
-                .collect()
+        let shutdown_hooks = async {
+            for hook in &arc_app.shutdown_hooks {
+                if let Err(e) = hook(arc_app.clone()).await {
+                    tracing::error!("shutdown hook failed: {e}");
+                }
+            }
         };
-
-        for h in &middlewares {
-            h.on_app_listen_mut(&mut self);
+        if tokio::time::timeout(arc_app.shutdown_timeout, shutdown_hooks)
+            .await
+            .is_err()
+        {
+            tracing::info!("Shutdown hooks timed out!");
         }
 
-        let arc_app = Arc::new(self);
-        for h in &middlewares {
-            h.on_app_listen_arc(&arc_app);
-        }
+        served
+    }
 
+    /// Accepts connections until `shutdown` is cancelled, then waits for them and the
+    /// background tasks to finish, up to the shutdown timeout.
+    async fn serve<A>(
+        arc_app: &Arc<App>,
+        addr: A,
+        shutdown: &CancellationToken,
+    ) -> Result<(), Error>
+    where
+        A: net::ToSocketAddrs + std::fmt::Debug + 'static,
+    {
         let addr = addr
             .to_socket_addrs()?
             .next()
@@ -281,25 +436,54 @@ impl App {
         };
         tracing::info!("Http app listening on http://{}", addr);
 
+        let background_tasks: Vec<_> = arc_app
+            .background_tasks
+            .iter()
+            .map(|task| tokio::spawn(task(shutdown.clone())))
+            .collect();
+
         let server = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new());
         let graceful = hyper_util::server::graceful::GracefulShutdown::new();
 
         let _ = shutdown
             .run_until_cancelled(async {
                 loop {
-                    let Ok((stream, peer_addr)) = listener.accept().await else {
+                    let Ok((mut stream, mut peer_addr)) = listener.accept().await else {
                         continue;
                     };
-                    let io = TokioIo::new(stream);
                     let app = arc_app.clone();
-                    let service = hyper::service::service_fn(move |req| {
-                        handle_request(req, app.clone(), peer_addr)
-                    });
-
-                    let conn = server.serve_connection_with_upgrades(io, service);
-                    let fut = graceful.watch(conn.into_owned());
+                    let server = server.clone();
+                    let watcher = graceful.watcher();
                     tokio::spawn(async move {
-                        if let Err(e) = fut.await {
+                        if app.proxy_protocol {
+                            let header = tokio::time::timeout(
+                                proxy_protocol::READ_TIMEOUT,
+                                proxy_protocol::read_header(&mut stream),
+                            )
+                            .await;
+                            match header {
+                                Ok(Ok(Some(addr))) => peer_addr = addr,
+                                Ok(Ok(None)) => {}
+                                Ok(Err(e)) => {
+                                    tracing::debug!("rejected connection from {peer_addr}: {e}");
+                                    return;
+                                }
+                                Err(_) => {
+                                    tracing::debug!(
+                                        "PROXY protocol header from {peer_addr} timed out"
+                                    );
+                                    return;
+                                }
+                            }
+                        }
+
+                        let io = TokioIo::new(stream);
+                        let service = hyper::service::service_fn(move |req| {
+                            handle_request(req.map(HttpBody::from_body), app.clone(), peer_addr)
+                        });
+
+                        let conn = server.serve_connection_with_upgrades(io, service);
+                        if let Err(e) = watcher.watch(conn.into_owned()).await {
                             tracing::trace!("connection failed: {e:?}");
                         }
                     });
@@ -314,13 +498,71 @@ impl App {
             arc_app.shutdown_timeout
         );
 
-        match tokio::time::timeout(arc_app.shutdown_timeout, graceful.shutdown()).await {
-            Ok(_) => tracing::info!("All connections closed!"),
-            Err(_) => tracing::info!("Shutdown timed out!"),
+        let shutdown_all = async {
+            graceful.shutdown().await;
+            tracing::info!("All connections closed!");
+
+            for task in background_tasks {
+                if let Err(e) = task.await {
+                    tracing::error!("background task failed: {e}");
+                }
+            }
+        };
+
+        if tokio::time::timeout(arc_app.shutdown_timeout, shutdown_all)
+            .await
+            .is_err()
+        {
+            tracing::info!("Shutdown timed out!");
         }
 
         Ok(())
     }
+
+    /// Turns the app into a service that can be served by your own hyper (or tower) stack,
+    /// instead of using [`App::listen`].
+    ///
+    /// Builds the router and runs the `on_app_listen_*` hooks, same as `listen` does.
+    pub fn into_service(self) -> Result<AppService, Error> {
+        let shutdown = self.shutdown.clone();
+        Ok(AppService {
+            app: self.prepare(shutdown)?,
+        })
+    }
+
+    /// Builds the router and runs the `on_app_listen_*` hooks of every handler.
+    fn prepare(mut self, shutdown: CancellationToken) -> Result<Arc<App>, Error> {
+        if self.dump_routes {
+            tracing::info!("App Router: {:#?}", self.router);
+        }
+
+        self.built_router = self.router.build()?;
+
+        self.shutdown = shutdown;
+
+        let middlewares: Vec<_> = {
+            let mut called = HashSet::new();
+            self.router
+                .flatten_routers()
+                .iter()
+                .flat_map(|(_, m)| m.values())
+                .flat_map(|h| h.iter())
+                .filter(|h| called.insert((h.type_id(), h.on_app_listen_key())))
+                .cloned()
+                .collect()
+        };
+
+        for h in &middlewares {
+            h.on_app_listen_mut(&mut self);
+        }
+
+        let arc_app = Arc::new(self);
+        for h in &middlewares {
+            h.on_app_listen_arc(&arc_app);
+        }
+
+        Ok(arc_app)
+    }
 }
 
 impl Clone for App {
@@ -337,12 +579,19 @@ impl Clone for App {
             dump_routes: self.dump_routes,
             body_limit: self.body_limit,
             proxy_header_fn: self.proxy_header_fn.clone(),
+            trusted_proxies: self.trusted_proxies.clone(),
+            proxy_protocol: self.proxy_protocol,
+            startup_hooks: self.startup_hooks.clone(),
+            shutdown_hooks: self.shutdown_hooks.clone(),
+            background_tasks: self.background_tasks.clone(),
+            shutdown_signals: self.shutdown_signals.clone(),
+            reload_hook: self.reload_hook.clone(),
         }
     }
 }
 
-async fn handle_request(
-    request: HyperRequest<IncomingBody>,
+pub(crate) async fn handle_request(
+    request: HyperRequest<HttpBody>,
     app: Arc<App>,
     peer_addr: net::SocketAddr,
 ) -> Result<HttpResponse, NoResponse> {
@@ -358,16 +607,17 @@ async fn handle_request(
         }
     };
 
-    let handlers = matched_route.value;
-    let handlers = handlers
-        .get(request.method())
-        .or_else(|| {
-            (request.method() == http::Method::HEAD)
-                .then(|| handlers.get(&http::Method::GET))
-                .flatten()
-        })
-        .or_else(|| handlers.get(&ALL));
-    let Some(handlers) = handlers else {
+    let route = matched_route.value;
+    let method = if route.handlers.contains_key(request.method()) {
+        request.method()
+    } else if request.method() == http::Method::HEAD
+        && route.handlers.contains_key(&http::Method::GET)
+    {
+        &http::Method::GET
+    } else {
+        &*ALL
+    };
+    let Some(handlers) = route.handlers.get(method) else {
         tracing::debug!(
             "requested method not allowed: {} {}",
             request.method(),
@@ -377,6 +627,7 @@ async fn handle_request(
         return Ok(response);
     };
     let handlers = handlers.clone();
+    let route_info = route.info(method);
 
     let params = matched_route
         .params
@@ -384,7 +635,7 @@ async fn handle_request(
         .map(|(k, v)| (SmolStr::new(k), SmolStr::new(v)))
         .collect();
 
-    let req = Request::new(app.clone(), request, params, peer_addr);
+    let req = Request::new(app.clone(), request, params, route_info, peer_addr);
     let res = Response::from_response(app, response);
 
     let mut c = crate::ctx::Ctx::new(req, res, handlers);
@@ -394,7 +645,22 @@ async fn handle_request(
         return Err(NoResponse);
     }
 
-    if c.req.method() == http::Method::HEAD {
+    if c.req.is_head() {
+        // hyper only derives the length from the body, keep the one a GET would have sent.
+        // Responses that never have a body must not get one.
+        let status = c.res.inner.status();
+        let has_body = !status.is_informational()
+            && status != http::StatusCode::NO_CONTENT
+            && status != http::StatusCode::NOT_MODIFIED;
+        if has_body
+            && !c.res.headers().contains_key(http::header::CONTENT_LENGTH)
+            && let Some(len) = http_body::Body::size_hint(c.res.inner.body()).exact()
+        {
+            c.res
+                .inner
+                .headers_mut()
+                .insert(http::header::CONTENT_LENGTH, len.into());
+        }
         *c.res.inner.body_mut() = HttpBody::default();
     }
 
@@ -437,7 +703,7 @@ fn normalize_path(s: &str) -> std::borrow::Cow<'_, str> {
 }
 
 #[derive(Debug)]
-struct NoResponse;
+pub(crate) struct NoResponse;
 
 impl std::fmt::Display for NoResponse {
     fn fmt(&self, _: &mut std::fmt::Formatter) -> std::fmt::Result {
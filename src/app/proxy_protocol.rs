@@ -0,0 +1,296 @@
+//! PROXY protocol v1 and v2 headers, sent by load balancers like HAProxy and AWS NLB
+//! in front of the connection to pass on the client's address.
+//!
+//! https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Longest possible v1 header, including the CRLF
+const V1_MAX_LEN: usize = 107;
+
+pub(crate) const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("PROXY protocol: {msg}"))
+}
+
+/// Reads the header off the start of the stream, leaving the rest for hyper.
+///
+/// Returns the client's address, or `None` when the header doesn't carry one
+/// (health checks by the proxy itself, `UNKNOWN` or unix sockets).
+pub(crate) async fn read_header<S>(stream: &mut S) -> io::Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    // Both versions are longer than this, so it never reads past the header
+    let mut start = [0u8; 12];
+    stream.read_exact(&mut start).await?;
+
+    if start == V2_SIGNATURE {
+        read_v2(stream).await
+    } else if start.starts_with(b"PROXY ") {
+        read_v1(stream, &start).await
+    } else {
+        Err(invalid("missing header"))
+    }
+}
+
+async fn read_v1<S>(stream: &mut S, start: &[u8]) -> io::Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut line = start.to_vec();
+    // One byte at a time, anything after the CRLF belongs to hyper
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err(invalid("v1 header too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2]).map_err(|_| invalid("not UTF-8"))?;
+    let mut parts = line.split(' ').skip(1);
+    match parts.next() {
+        Some("TCP4" | "TCP6") => {}
+        Some("UNKNOWN") => return Ok(None),
+        _ => return Err(invalid("unknown v1 protocol")),
+    }
+
+    let src: IpAddr = parts
+        .next()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| invalid("bad v1 source address"))?;
+    let _dst = parts.next();
+    let port: u16 = parts
+        .next()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| invalid("bad v1 source port"))?;
+
+    Ok(Some(SocketAddr::new(src, port)))
+}
+
+async fn read_v2<S>(stream: &mut S) -> io::Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut head = [0u8; 4];
+    stream.read_exact(&mut head).await?;
+    let [version_command, family, len_hi, len_lo] = head;
+
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported version"));
+    }
+
+    // The address block is always read, so the stream is left right after the header
+    let mut block = vec![0u8; u16::from_be_bytes([len_hi, len_lo]) as usize];
+    stream.read_exact(&mut block).await?;
+
+    match version_command & 0x0F {
+        // LOCAL, the proxy's own connection
+        0x0 => return Ok(None),
+        // PROXY
+        0x1 => {}
+        _ => return Err(invalid("unknown v2 command")),
+    }
+
+    let addr = match family >> 4 {
+        // AF_INET
+        0x1 if block.len() >= 12 => {
+            let ip = Ipv4Addr::new(block[0], block[1], block[2], block[3]);
+            SocketAddr::new(ip.into(), u16::from_be_bytes([block[8], block[9]]))
+        }
+        // AF_INET6
+        0x2 if block.len() >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&block[..16]);
+            let ip = Ipv6Addr::from(octets);
+            SocketAddr::new(ip.into(), u16::from_be_bytes([block[32], block[33]]))
+        }
+        0x1 | 0x2 => return Err(invalid("v2 address block too short")),
+        // AF_UNSPEC and AF_UNIX
+        _ => return Ok(None),
+    };
+
+    Ok(Some(addr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads the header off `input`, returning the result and what's left for hyper.
+    async fn read(input: &[u8]) -> (io::Result<Option<SocketAddr>>, &[u8]) {
+        let mut stream = input;
+        let res = read_header(&mut stream).await;
+        (res, stream)
+    }
+
+    fn v2(version_command: u8, family: u8, block: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[version_command, family]);
+        header.extend_from_slice(&(block.len() as u16).to_be_bytes());
+        header.extend_from_slice(block);
+        header
+    }
+
+    #[tokio::test]
+    async fn v1_tcp4() {
+        let (res, rest) = read(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET /").await;
+        assert_eq!(res.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn v1_tcp6() {
+        let (res, rest) = read(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\nGET /").await;
+        assert_eq!(res.unwrap(), Some("[2001:db8::1]:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn v1_unknown() {
+        let (res, rest) = read(b"PROXY UNKNOWN\r\nGET /").await;
+        assert_eq!(res.unwrap(), None);
+        assert_eq!(rest, b"GET /");
+
+        let (res, rest) = read(b"PROXY UNKNOWN ::1 ::1 1 2\r\nGET /").await;
+        assert_eq!(res.unwrap(), None);
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn v1_longest_line() {
+        let mut line = b"PROXY UNKNOWN ".to_vec();
+        line.resize(V1_MAX_LEN - 2, b'x');
+        line.extend_from_slice(b"\r\nGET /");
+        let (res, rest) = read(&line).await;
+        assert_eq!(res.unwrap(), None);
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn v1_line_too_long() {
+        let mut line = b"PROXY UNKNOWN ".to_vec();
+        line.resize(V1_MAX_LEN - 1, b'x');
+        line.extend_from_slice(b"\r\nGET /");
+        let (res, _) = read(&line).await;
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn v1_missing_crlf() {
+        let (res, _) = read(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443").await;
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+        // A bare LF doesn't end the line, so it runs into the length limit
+        let mut line = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\nGET / HTTP/1.1\n".to_vec();
+        line.resize(200, b'x');
+        let (res, _) = read(&line).await;
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn v1_bad_fields() {
+        for line in [
+            &b"PROXY UDP4 192.0.2.1 198.51.100.1 56324 443\r\n"[..],
+            b"PROXY TCP4 192.0.2 198.51.100.1 56324 443\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 99999 443\r\n",
+        ] {
+            let (res, _) = read(line).await;
+            assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_header() {
+        let (res, _) = read(b"GET / HTTP/1.1\r\n\r\n").await;
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn v2_local() {
+        let mut input = v2(0x20, 0x00, &[]);
+        input.extend_from_slice(b"GET /");
+        let (res, rest) = read(&input).await;
+        assert_eq!(res.unwrap(), None);
+        assert_eq!(rest, b"GET /");
+
+        // The address block is skipped even when it's ignored
+        let mut input = v2(
+            0x20,
+            0x11,
+            &[192, 0, 2, 1, 198, 51, 100, 1, 0xDC, 0x04, 0x01, 0xBB],
+        );
+        input.extend_from_slice(b"GET /");
+        let (res, rest) = read(&input).await;
+        assert_eq!(res.unwrap(), None);
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn v2_proxy_inet() {
+        // Followed by a TLV, which is skipped with the rest of the block
+        let block = [
+            192, 0, 2, 1, 198, 51, 100, 1, 0xDC, 0x04, 0x01, 0xBB, 0x04, 0x00, 0x01, 0x00,
+        ];
+        let mut input = v2(0x21, 0x11, &block);
+        input.extend_from_slice(b"GET /");
+        let (res, rest) = read(&input).await;
+        assert_eq!(res.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn v2_proxy_inet6() {
+        let src: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let dst: Ipv6Addr = "2001:db8::2".parse().unwrap();
+        let mut block = src.octets().to_vec();
+        block.extend_from_slice(&dst.octets());
+        block.extend_from_slice(&[0xDC, 0x04, 0x01, 0xBB]);
+        let mut input = v2(0x21, 0x21, &block);
+        input.extend_from_slice(b"GET /");
+        let (res, rest) = read(&input).await;
+        assert_eq!(res.unwrap(), Some("[2001:db8::1]:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn v2_proxy_unix() {
+        let mut input = v2(0x21, 0x31, &[0; 216]);
+        input.extend_from_slice(b"GET /");
+        let (res, rest) = read(&input).await;
+        assert_eq!(res.unwrap(), None);
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn v2_address_block_too_short() {
+        for input in [v2(0x21, 0x11, &[0; 11]), v2(0x21, 0x21, &[0; 12])] {
+            let (res, _) = read(&input).await;
+            assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[tokio::test]
+    async fn v2_truncated() {
+        let mut input = v2(0x21, 0x11, &[0; 12]);
+        input.truncate(input.len() - 1);
+        let (res, _) = read(&input).await;
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn v2_bad_version_or_command() {
+        for input in [v2(0x11, 0x11, &[0; 12]), v2(0x22, 0x11, &[0; 12])] {
+            let (res, _) = read(&input).await;
+            assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
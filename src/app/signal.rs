@@ -0,0 +1,64 @@
+use std::io;
+
+use tokio_util::sync::CancellationToken;
+
+/// Process signals that can trigger a graceful shutdown, see [`App::shutdown_signals`](crate::prelude::App::shutdown_signals).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Signal {
+    /// SIGINT on unix, CTRL+C everywhere else.
+    Interrupt,
+    /// SIGTERM, sent by systemd, Docker and Kubernetes. (unix only)
+    Terminate,
+    /// SIGQUIT (unix only)
+    Quit,
+    /// SIGHUP (unix only)
+    Hangup,
+    /// Any other signal number. (unix only)
+    Raw(i32),
+}
+
+impl Signal {
+    #[cfg(unix)]
+    fn kind(self) -> tokio::signal::unix::SignalKind {
+        use tokio::signal::unix::SignalKind;
+        match self {
+            Signal::Interrupt => SignalKind::interrupt(),
+            Signal::Terminate => SignalKind::terminate(),
+            Signal::Quit => SignalKind::quit(),
+            Signal::Hangup => SignalKind::hangup(),
+            Signal::Raw(n) => SignalKind::from_raw(n),
+        }
+    }
+}
+
+/// Installs the signal handlers, cancelling `token` as soon as any of `signals` is received.
+pub(crate) fn cancel_on(signals: &[Signal], token: &CancellationToken) -> io::Result<()> {
+    for &signal in signals {
+        let t = token.clone();
+
+        #[cfg(unix)]
+        {
+            let mut stream = tokio::signal::unix::signal(signal.kind())?;
+            tokio::spawn(async move {
+                if let Some(Some(())) = t.run_until_cancelled(stream.recv()).await {
+                    tracing::info!("Received {signal:?} signal");
+                    t.cancel();
+                }
+            });
+        }
+
+        #[cfg(not(unix))]
+        match signal {
+            Signal::Interrupt => {
+                tokio::spawn(async move {
+                    if let Some(Ok(())) = t.run_until_cancelled(tokio::signal::ctrl_c()).await {
+                        tracing::info!("Received {signal:?} signal");
+                        t.cancel();
+                    }
+                });
+            }
+            _ => tracing::warn!("{signal:?} signal is not supported on this platform"),
+        }
+    }
+    Ok(())
+}
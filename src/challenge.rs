@@ -0,0 +1,4 @@
+/// Escapes `value` for a quoted-string parameter of a `WWW-Authenticate` challenge.
+pub(crate) fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -29,6 +29,34 @@ impl Ctx {
         }
     }
 
+    /// A copy that runs the remaining handlers on its own, with a fresh response and
+    /// without the request body. Used to regenerate responses in the background.
+    ///
+    /// The copy is anonymous: it gets empty cookies and session, and the request's
+    /// `Cookie` and `Authorization` headers are removed.
+    #[cfg(feature = "middleware-cache")]
+    pub(crate) fn fork(&self) -> Self {
+        let res = Response::from_response(
+            self.req.app.clone(),
+            http::Response::new(crate::response::HttpBody::default()),
+        );
+        let mut req = self.req.fork();
+        req.headers_mut().remove(http::header::COOKIE);
+        req.headers_mut().remove(http::header::AUTHORIZATION);
+        Self {
+            index_handler: self.index_handler,
+            ..Self::new(req, res, self.handlers.clone())
+        }
+    }
+
+    /// Streams a file as the response, answering the request's range and conditional
+    /// headers. `HEAD` requests only get the headers, without the file being opened.
+    ///
+    /// See [`Response::send_file_ranged`].
+    pub async fn send_file(&mut self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        self.res.send_file_ranged(&self.req, path).await
+    }
+
     #[inline]
     pub fn handlers(&self) -> &[DynHandlerRun] {
         &self.handlers
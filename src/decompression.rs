@@ -0,0 +1,175 @@
+use std::{
+    io::{self, Write},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use http::{HeaderMap, header};
+use http_body::{Body as HttpBodyTrait, Frame, SizeHint};
+
+use crate::{
+    request::BodyError,
+    response::{BoxError, HttpBody},
+};
+
+/// Returns the decoder for the request's `Content-Encoding`, `None` if the body is not encoded.
+pub(crate) fn decoder_for(headers: &HeaderMap, limit: usize) -> Result<Option<Decoder>, BodyError> {
+    let Some(value) = headers.get(header::CONTENT_ENCODING) else {
+        return Ok(None);
+    };
+    let value = value
+        .to_str()
+        .map_err(|_| BodyError::UnsupportedEncoding)?
+        .trim();
+
+    let output = LimitedWriter {
+        buf: Vec::new(),
+        written: 0,
+        limit,
+    };
+    let decoder = if value.eq_ignore_ascii_case("identity") || value.is_empty() {
+        return Ok(None);
+    } else if value.eq_ignore_ascii_case("gzip") || value.eq_ignore_ascii_case("x-gzip") {
+        Decoder::Gzip(flate2::write::GzDecoder::new(output))
+    } else if value.eq_ignore_ascii_case("deflate") {
+        Decoder::Deflate(flate2::write::ZlibDecoder::new(output))
+    } else if value.eq_ignore_ascii_case("br") {
+        Decoder::Brotli(Box::new(brotli::DecompressorWriter::new(output, 4096)))
+    } else {
+        return Err(BodyError::UnsupportedEncoding);
+    };
+    Ok(Some(decoder))
+}
+
+pub(crate) enum Decoder {
+    Gzip(flate2::write::GzDecoder<LimitedWriter>),
+    Deflate(flate2::write::ZlibDecoder<LimitedWriter>),
+    Brotli(Box<brotli::DecompressorWriter<LimitedWriter>>),
+}
+
+impl Decoder {
+    /// Decompresses `data` and returns whatever output the decoder produced so far.
+    fn write(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let out = match self {
+            Decoder::Gzip(d) => {
+                d.write_all(data)?;
+                d.get_mut()
+            }
+            Decoder::Deflate(d) => {
+                d.write_all(data)?;
+                d.get_mut()
+            }
+            Decoder::Brotli(d) => {
+                d.write_all(data)?;
+                d.get_mut()
+            }
+        };
+        Ok(std::mem::take(&mut out.buf))
+    }
+
+    fn finish(self) -> io::Result<Vec<u8>> {
+        let out = match self {
+            Decoder::Gzip(d) => d.finish()?,
+            Decoder::Deflate(d) => d.finish()?,
+            Decoder::Brotli(d) => d.into_inner().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "truncated brotli stream")
+            })?,
+        };
+        Ok(out.buf)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("decompressed body exceeds the body limit")]
+pub(crate) struct DecompressedTooLarge;
+
+/// Fails once more than `limit` bytes were written in total, so a small
+/// compressed body can't blow up in memory.
+pub(crate) struct LimitedWriter {
+    buf: Vec<u8>,
+    written: usize,
+    limit: usize,
+}
+
+impl Write for LimitedWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.written + data.len() > self.limit {
+            return Err(io::Error::other(DecompressedTooLarge));
+        }
+        self.written += data.len();
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub(crate) struct DecodedBody {
+    inner: HttpBody,
+    decoder: Option<Decoder>,
+}
+
+impl DecodedBody {
+    pub(crate) fn new(inner: HttpBody, decoder: Decoder) -> Self {
+        Self {
+            inner,
+            decoder: Some(decoder),
+        }
+    }
+}
+
+impl HttpBodyTrait for DecodedBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        loop {
+            let Some(decoder) = this.decoder.as_mut() else {
+                return Poll::Ready(None);
+            };
+
+            match Pin::new(&mut this.inner).poll_frame(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(Some(Ok(frame))) => {
+                    // Request trailers are dropped, there is nothing meaningful to do with them here
+                    let Ok(data) = frame.into_data() else {
+                        continue;
+                    };
+                    let out = decoder.write(&data).map_err(into_box_error)?;
+                    if !out.is_empty() {
+                        return Poll::Ready(Some(Ok(Frame::data(out.into()))));
+                    }
+                }
+                Poll::Ready(None) => {
+                    let out = this
+                        .decoder
+                        .take()
+                        .map_or(Ok(Vec::new()), Decoder::finish)
+                        .map_err(into_box_error)?;
+                    return Poll::Ready(Some(Ok(Frame::data(out.into()))));
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::new()
+    }
+}
+
+// Unwraps the io error so `From<BodyError> for StatusError` can downcast to `DecompressedTooLarge`.
+fn into_box_error(e: io::Error) -> BoxError {
+    if e.get_ref().is_some() {
+        e.into_inner().unwrap()
+    } else {
+        Box::new(e)
+    }
+}
@@ -16,6 +16,9 @@ pub enum Error {
 
     #[error("invalid header value: {0}")]
     InvalidHeaderValue(#[from] http::header::InvalidHeaderValue),
+
+    #[error("startup hook failed: {0}")]
+    Startup(crate::response::BoxError),
 }
 
 impl From<Infallible> for Error {
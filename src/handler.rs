@@ -37,6 +37,12 @@ pub trait Handler<Ctx> {
     #[allow(unused_variables)]
     fn on_app_listen_arc(&self, app: &Arc<App>) {}
 
+    /// The `on_app_listen_*` hooks only run for the first handler of each type in the app,
+    /// handlers returning different keys here each get theirs run, e.g. one per mount.
+    fn on_app_listen_key(&self) -> Option<String> {
+        None
+    }
+
     fn state(&self) -> &dyn std::any::Any {
         &()
     }
@@ -100,6 +106,7 @@ pub trait HandlerRun: Send + Sync + Debug {
 
     fn on_app_listen_mut(&self, _: &mut crate::app::App);
     fn on_app_listen_arc(&self, _: &Arc<crate::app::App>);
+    fn on_app_listen_key(&self) -> Option<String>;
 
     fn type_id(&self) -> std::any::TypeId
     where
@@ -144,6 +151,10 @@ where
         self.f.on_app_listen_arc(a);
     }
 
+    fn on_app_listen_key(&self) -> Option<String> {
+        self.f.on_app_listen_key()
+    }
+
     fn type_id(&self) -> std::any::TypeId {
         self.f.type_id()
     }
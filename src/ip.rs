@@ -0,0 +1,219 @@
+use std::net::{IpAddr, SocketAddr};
+
+use ipnet::IpNet;
+
+/// Parses a CIDR range, or a single address as a range containing only itself.
+#[track_caller]
+pub(crate) fn parse_net(s: &str) -> IpNet {
+    let s = s.trim();
+    s.parse::<IpNet>()
+        .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+        .unwrap_or_else(|_| panic!("invalid IP address or CIDR range: {s:?}"))
+}
+
+/// Parses a node of a forwarding header: `1.2.3.4`, `1.2.3.4:80`, `[::1]`, `[::1]:80` or `::1`.
+///
+/// Returns `None` for `unknown` and obfuscated identifiers like `_hidden`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    // `[::1]` without a port
+    node.strip_prefix('[')?.strip_suffix(']')?.parse().ok()
+}
+
+/// The `for=` nodes of RFC 7239 `Forwarded` headers, from the client to the last proxy.
+pub(crate) fn forwarded<'a>(values: impl Iterator<Item = &'a str>) -> Vec<Option<IpAddr>> {
+    values
+        .flat_map(|v| v.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                key.trim()
+                    .eq_ignore_ascii_case("for")
+                    .then(|| parse_node(value))
+            })
+        })
+        .collect()
+}
+
+/// The addresses of `X-Forwarded-For` style headers, from the client to the last proxy.
+pub(crate) fn x_forwarded_for<'a>(values: impl Iterator<Item = &'a str>) -> Vec<Option<IpAddr>> {
+    values
+        .flat_map(|v| v.split(','))
+        .filter(|node| !node.trim().is_empty())
+        .map(parse_node)
+        .collect()
+}
+
+/// Walks `chain` from the right, skipping trusted proxies, and returns the first address
+/// that isn't one. Clients can prepend anything to the chain, so only what our own proxies
+/// appended is believed.
+///
+/// Stops at the last trusted hop if the chain has a node that isn't an address, and
+/// returns the left-most address if every hop is trusted.
+pub(crate) fn client_ip(peer: IpAddr, chain: &[Option<IpAddr>], trusted: &[IpNet]) -> IpAddr {
+    let mut ip = peer;
+    for node in chain.iter().rev() {
+        if !in_ranges(ip, trusted) {
+            break;
+        }
+        match node {
+            Some(node) => ip = *node,
+            None => break,
+        }
+    }
+    ip
+}
+
+/// Whether `ip` is in any of `ranges`, IPv4-mapped IPv6 addresses match IPv4 ranges.
+pub(crate) fn in_ranges(ip: IpAddr, ranges: &[IpNet]) -> bool {
+    let ip = ip.to_canonical();
+    ranges.iter().any(|net| net.contains(&ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn nets(ranges: &[&str]) -> Vec<IpNet> {
+        ranges.iter().map(|r| parse_net(r)).collect()
+    }
+
+    #[test]
+    fn parse_net_single_address() {
+        assert_eq!(parse_net(" 10.0.0.1 "), "10.0.0.1/32".parse().unwrap());
+        assert_eq!(parse_net("::1"), "::1/128".parse().unwrap());
+        assert_eq!(parse_net("10.0.0.0/8"), "10.0.0.0/8".parse().unwrap());
+    }
+
+    #[test]
+    fn x_forwarded_for_nodes() {
+        let chain = x_forwarded_for(["203.0.113.7, 10.0.0.1", " ,[::1]:80,unknown"].into_iter());
+        assert_eq!(
+            chain,
+            [
+                Some(ip("203.0.113.7")),
+                Some(ip("10.0.0.1")),
+                Some(ip("::1")),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn forwarded_nodes() {
+        let chain = forwarded(
+            [
+                "for=192.0.2.60;proto=http;by=203.0.113.43",
+                r#"For="[2001:db8:cafe::17]:4711", for=unknown"#,
+                "for=_hidden, proto=https",
+            ]
+            .into_iter(),
+        );
+        assert_eq!(
+            chain,
+            [
+                Some(ip("192.0.2.60")),
+                Some(ip("2001:db8:cafe::17")),
+                None,
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn forwarded_ipv6_forms() {
+        for value in [
+            r#"for="[2001:db8::1]:4711""#,
+            r#"for="[2001:db8::1]""#,
+            "for=[2001:db8::1]",
+            "for=2001:db8::1",
+        ] {
+            assert_eq!(
+                forwarded([value].into_iter()),
+                [Some(ip("2001:db8::1"))],
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn client_ip_untrusted_peer() {
+        let trusted = nets(&["10.0.0.0/8"]);
+        let chain = [Some(ip("203.0.113.7"))];
+        assert_eq!(
+            client_ip(ip("198.51.100.1"), &chain, &trusted),
+            ip("198.51.100.1")
+        );
+    }
+
+    #[test]
+    fn client_ip_right_most_untrusted_hop() {
+        let trusted = nets(&["10.0.0.0/8"]);
+        let chain = [
+            Some(ip("1.1.1.1")),
+            Some(ip("203.0.113.7")),
+            Some(ip("10.0.0.2")),
+        ];
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &chain, &trusted),
+            ip("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn client_ip_ignores_spoofed_left_entries() {
+        let trusted = nets(&["10.0.0.0/8"]);
+        // The client sent `10.0.0.9, 127.0.0.1` itself, our proxy appended the address it saw
+        let chain = x_forwarded_for(["10.0.0.9, 127.0.0.1, 203.0.113.7"].into_iter());
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &chain, &trusted),
+            ip("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn client_ip_all_trusted() {
+        let trusted = nets(&["10.0.0.0/8"]);
+        let chain = [Some(ip("10.0.0.3")), Some(ip("10.0.0.2"))];
+        assert_eq!(client_ip(ip("10.0.0.1"), &chain, &trusted), ip("10.0.0.3"));
+    }
+
+    #[test]
+    fn client_ip_stops_at_unknown_node() {
+        let trusted = nets(&["10.0.0.0/8"]);
+        let chain = forwarded(["for=203.0.113.7, for=unknown, for=10.0.0.2"].into_iter());
+        assert_eq!(client_ip(ip("10.0.0.1"), &chain, &trusted), ip("10.0.0.2"));
+
+        let chain = forwarded(["for=203.0.113.7, for=_hidden"].into_iter());
+        assert_eq!(client_ip(ip("10.0.0.1"), &chain, &trusted), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn client_ip_empty_chain() {
+        let trusted = nets(&["10.0.0.0/8"]);
+        assert_eq!(client_ip(ip("10.0.0.1"), &[], &trusted), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn ipv4_mapped_peer_matches_ipv4_ranges() {
+        let trusted = nets(&["10.0.0.0/8"]);
+        assert!(in_ranges(ip("::ffff:10.0.0.1"), &trusted));
+        assert!(!in_ranges(ip("::ffff:203.0.113.7"), &trusted));
+
+        let chain = [Some(ip("203.0.113.7"))];
+        assert_eq!(
+            client_ip(ip("::ffff:10.0.0.1"), &chain, &trusted),
+            ip("203.0.113.7")
+        );
+    }
+}
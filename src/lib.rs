@@ -6,13 +6,25 @@ pub use hyper;
 mod any_map;
 mod app;
 
+#[cfg(any(feature = "middleware-auth", feature = "middleware-jwt"))]
+mod challenge;
 mod ctx;
+#[cfg(feature = "decompression")]
+mod decompression;
 mod error;
 mod handler;
 mod into_response;
+mod ip;
+#[cfg(any(feature = "middleware-compression", feature = "static_files"))]
+mod negotiate;
+mod range;
 mod request;
 mod response;
 mod router;
+#[cfg(feature = "serve_dir")]
+mod serve_dir;
+mod service;
+mod sse;
 mod status_error;
 
 #[cfg(feature = "static_files")]
@@ -21,10 +33,21 @@ mod static_files;
 #[cfg(feature = "websocket")]
 mod websocket;
 
+#[cfg(feature = "serve_dir")]
+pub use serve_dir::{DotFiles, ServeDir};
+
+pub use sse::{Event, SseOptions};
+
 #[cfg(feature = "static_files")]
 pub use static_files::StaticFiles;
 
 pub mod middlewares {
+    #[cfg(feature = "middleware-auth")]
+    pub mod auth;
+
+    #[cfg(feature = "middleware-cache")]
+    pub mod cache;
+
     #[cfg(feature = "middleware-cookie")]
     pub mod cookie;
 
@@ -34,16 +57,49 @@ pub mod middlewares {
     #[cfg(feature = "middleware-csrf")]
     pub mod csrf;
 
+    #[cfg(feature = "middleware-etag")]
+    pub(crate) mod etag;
+
+    #[cfg(feature = "middleware-ip_filter")]
+    pub(crate) mod ip_filter;
+
+    #[cfg(feature = "middleware-jwt")]
+    pub mod jwt;
+
     #[cfg(feature = "middleware-logging")]
-    pub(crate) mod logging;
+    pub mod logging;
 
     #[cfg(feature = "middleware-catch_panic")]
     pub(crate) mod catch_panic;
 
     #[cfg(feature = "middleware-body_limit")]
     pub(crate) mod body_limit;
+
+    #[cfg(feature = "middleware-compression")]
+    pub mod compression;
+
+    #[cfg(feature = "middleware-cors")]
+    pub(crate) mod cors;
+
+    #[cfg(feature = "middleware-metrics")]
+    pub mod metrics;
+
+    #[cfg(feature = "middleware-rate_limit")]
+    pub mod rate_limit;
+
+    #[cfg(feature = "middleware-request_id")]
+    pub mod request_id;
+
+    #[cfg(feature = "middleware-security_headers")]
+    pub mod security_headers;
 }
 
+#[cfg(feature = "middleware-auth")]
+pub use middlewares::auth::{BasicAuthMiddleware, BearerAuthMiddleware};
+
+#[cfg(feature = "middleware-cache")]
+pub use middlewares::cache::CacheMiddleware;
+
 #[cfg(feature = "middleware-cookie")]
 pub use middlewares::cookie::CookieMiddleware;
 
@@ -53,6 +109,15 @@ pub use middlewares::session::SessionMiddleware;
 #[cfg(feature = "middleware-csrf")]
 pub use middlewares::csrf::CsrfMiddleware;
 
+#[cfg(feature = "middleware-etag")]
+pub use middlewares::etag::ETagMiddleware;
+
+#[cfg(feature = "middleware-ip_filter")]
+pub use middlewares::ip_filter::IpFilterMiddleware;
+
+#[cfg(feature = "middleware-jwt")]
+pub use middlewares::jwt::JwtMiddleware;
+
 #[cfg(feature = "middleware-logging")]
 pub use middlewares::logging::LoggingMiddleware;
 
@@ -62,6 +127,24 @@ pub use middlewares::catch_panic::CatchPanicMiddleware;
 #[cfg(feature = "middleware-body_limit")]
 pub use middlewares::body_limit::BodyLimitMiddleware;
 
+#[cfg(feature = "middleware-compression")]
+pub use middlewares::compression::CompressionMiddleware;
+
+#[cfg(feature = "middleware-cors")]
+pub use middlewares::cors::CorsMiddleware;
+
+#[cfg(feature = "middleware-metrics")]
+pub use middlewares::metrics::MetricsMiddleware;
+
+#[cfg(feature = "middleware-rate_limit")]
+pub use middlewares::rate_limit::RateLimitMiddleware;
+
+#[cfg(feature = "middleware-request_id")]
+pub use middlewares::request_id::RequestIdMiddleware;
+
+#[cfg(feature = "middleware-security_headers")]
+pub use middlewares::security_headers::SecurityHeadersMiddleware;
+
 pub fn all() -> http::Method {
     http::Method::from_bytes(b"*******").expect("failed to create ALL method") // should never happen
 }
@@ -74,6 +157,9 @@ pub mod prelude {
     pub use crate::error::Error as MawError;
     pub use crate::handler::Handler;
     pub use crate::router::{Router, WithState};
+    #[cfg(feature = "serve_dir")]
+    pub use crate::serve_dir::ServeDir;
+    pub use crate::sse::{Event, SseOptions};
     #[cfg(feature = "static_files")]
     pub use crate::static_files::StaticFiles;
     pub use crate::status_error::StatusError;
@@ -88,7 +174,10 @@ pub mod prelude {
 }
 
 pub use crate::into_response::IntoResponse;
-pub use crate::response::{BoxError, HttpBody};
+pub use crate::response::{BoxError, HttpBody, ResponseBodyError};
+pub use crate::app::Signal;
+pub use crate::service::AppService;
+pub use crate::router::RouteInfo;
 #[cfg(feature = "middleware-cookie")]
 pub use postcard;
 pub use serde_json;
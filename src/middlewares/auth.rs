@@ -0,0 +1,285 @@
+use std::{fmt, pin::Pin, sync::Arc};
+
+use base64::{Engine as _, engine::general_purpose};
+use constant_time_eq::constant_time_eq;
+use http::header;
+
+use crate::{challenge::quote, ctx::Ctx, handler::Handler, prelude::StatusError, request::Request};
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+type Validator<C, I> = Arc<dyn Fn(C) -> BoxFuture<Option<I>> + Send + Sync>;
+
+/// The identity returned by the validator, stored in the request extensions.
+#[derive(Clone)]
+struct Identity<I>(I);
+
+/// Splits `<scheme> <credentials>`, the scheme is case-insensitive.
+///
+/// `None` when no credentials were sent for `scheme`, `Err` when they're empty.
+fn authorization<'a>(c: &'a Ctx, scheme: &str) -> Option<Result<&'a str, ()>> {
+    let value = c.req.header(header::AUTHORIZATION)?;
+    let (s, credentials) = value.trim().split_once(' ').unwrap_or((value.trim(), ""));
+    if !s.eq_ignore_ascii_case(scheme) {
+        return None;
+    }
+    match credentials.trim() {
+        "" => Some(Err(())),
+        credentials => Some(Ok(credentials)),
+    }
+}
+
+/// Credentials sent with the `Basic` scheme.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BasicCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl BasicCredentials {
+    fn parse(encoded: &str) -> Option<Self> {
+        let decoded = general_purpose::STANDARD.decode(encoded).ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        let (username, password) = decoded.split_once(':')?;
+        Some(Self {
+            username: username.to_string(),
+            password: password.to_string(),
+        })
+    }
+
+    /// Compares both fields in constant time.
+    pub fn matches(&self, username: &str, password: &str) -> bool {
+        let username = constant_time_eq(self.username.as_bytes(), username.as_bytes());
+        let password = constant_time_eq(self.password.as_bytes(), password.as_bytes());
+        username & password
+    }
+}
+
+/// HTTP Basic authentication (RFC 7617).
+///
+/// The validator gets the credentials and returns the identity of the user, which
+/// handlers read with `c.req.identity::<I>()`. Requests without valid credentials
+/// get a 401 with a `WWW-Authenticate: Basic` challenge.
+///
+/// ```rust,ignore
+/// BasicAuthMiddleware::new(async |creds: BasicCredentials| {
+///     db::check_password(&creds.username, &creds.password).await
+/// })
+/// ```
+pub struct BasicAuthMiddleware<I = String> {
+    realm: String,
+    validator: Validator<BasicCredentials, I>,
+}
+
+impl<I> Clone for BasicAuthMiddleware<I> {
+    fn clone(&self) -> Self {
+        Self {
+            realm: self.realm.clone(),
+            validator: self.validator.clone(),
+        }
+    }
+}
+
+impl<I> fmt::Debug for BasicAuthMiddleware<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BasicAuthMiddleware")
+            .field("realm", &self.realm)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I: Clone + Send + Sync + 'static> BasicAuthMiddleware<I> {
+    pub fn new<F, Fut>(validator: F) -> Self
+    where
+        F: Fn(BasicCredentials) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<I>> + Send + 'static,
+    {
+        Self {
+            realm: "Restricted".to_string(),
+            validator: Arc::new(move |creds| Box::pin(validator(creds))),
+        }
+    }
+
+    /// Default: "Restricted"
+    pub fn realm(mut self, realm: impl Into<String>) -> Self {
+        self.realm = realm.into();
+        self
+    }
+
+    fn challenge(&self) -> String {
+        format!("Basic realm=\"{}\", charset=\"UTF-8\"", quote(&self.realm))
+    }
+}
+
+impl BasicAuthMiddleware<String> {
+    /// Accepts a fixed set of username/password pairs, the identity is the username.
+    pub fn users<U, P>(users: impl IntoIterator<Item = (U, P)>) -> Self
+    where
+        U: Into<String>,
+        P: Into<String>,
+    {
+        let users: Arc<[(String, String)]> = users
+            .into_iter()
+            .map(|(u, p)| (u.into(), p.into()))
+            .collect();
+        Self::new(move |creds: BasicCredentials| {
+            // Check every pair, so timing doesn't reveal which usernames exist
+            let found = users
+                .iter()
+                .fold(false, |found, (u, p)| found | creds.matches(u, p));
+            async move { found.then_some(creds.username) }
+        })
+    }
+}
+
+impl<I: Clone + Send + Sync + 'static> Handler<&mut Ctx> for BasicAuthMiddleware<I> {
+    type Output = Result<(), StatusError>;
+
+    async fn call(&self, c: &mut Ctx) -> Self::Output {
+        let creds = match authorization(c, "Basic") {
+            Some(Ok(encoded)) => BasicCredentials::parse(encoded),
+            _ => None,
+        };
+
+        let identity = match creds {
+            Some(creds) => (self.validator)(creds).await,
+            None => None,
+        };
+
+        let Some(identity) = identity else {
+            c.res.header((header::WWW_AUTHENTICATE, self.challenge()));
+            return Err(StatusError::unauthorized());
+        };
+
+        c.req.extensions_mut().insert(Identity(identity));
+        c.next().await;
+        Ok(())
+    }
+}
+
+/// Bearer token authentication (RFC 6750).
+///
+/// The validator gets the token and returns the identity it belongs to, which
+/// handlers read with `c.req.identity::<I>()`. Failures get a `WWW-Authenticate: Bearer`
+/// challenge: 401 without an error code when no token was sent, 401 `invalid_token` when
+/// the validator rejects it and 400 `invalid_request` when the header is malformed.
+///
+/// ```rust,ignore
+/// BearerAuthMiddleware::new(async |token: String| db::find_api_key(&token).await)
+/// ```
+pub struct BearerAuthMiddleware<I = String> {
+    realm: String,
+    scope: Option<String>,
+    validator: Validator<String, I>,
+}
+
+impl<I> Clone for BearerAuthMiddleware<I> {
+    fn clone(&self) -> Self {
+        Self {
+            realm: self.realm.clone(),
+            scope: self.scope.clone(),
+            validator: self.validator.clone(),
+        }
+    }
+}
+
+impl<I> fmt::Debug for BearerAuthMiddleware<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BearerAuthMiddleware")
+            .field("realm", &self.realm)
+            .field("scope", &self.scope)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I: Clone + Send + Sync + 'static> BearerAuthMiddleware<I> {
+    pub fn new<F, Fut>(validator: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<I>> + Send + 'static,
+    {
+        Self {
+            realm: "Restricted".to_string(),
+            scope: None,
+            validator: Arc::new(move |token| Box::pin(validator(token))),
+        }
+    }
+
+    /// Default: "Restricted"
+    pub fn realm(mut self, realm: impl Into<String>) -> Self {
+        self.realm = realm.into();
+        self
+    }
+
+    /// Space separated scopes needed to access the resource, sent in challenges.
+    ///
+    /// Default: None
+    pub fn scope(mut self, scope: impl Into<String>) -> Self {
+        self.scope = Some(scope.into());
+        self
+    }
+
+    fn challenge(&self, error: Option<(&str, &str)>) -> String {
+        let mut challenge = format!("Bearer realm=\"{}\"", quote(&self.realm));
+        if let Some(scope) = &self.scope {
+            challenge.push_str(&format!(", scope=\"{}\"", quote(scope)));
+        }
+        if let Some((error, description)) = error {
+            challenge.push_str(&format!(
+                ", error=\"{error}\", error_description=\"{description}\""
+            ));
+        }
+        challenge
+    }
+}
+
+impl BearerAuthMiddleware<String> {
+    /// Accepts a fixed set of tokens, the identity is the token.
+    pub fn tokens<T: Into<String>>(tokens: impl IntoIterator<Item = T>) -> Self {
+        let tokens: Arc<[String]> = tokens.into_iter().map(Into::into).collect();
+        Self::new(move |token: String| {
+            let found = tokens.iter().fold(false, |found, t| {
+                found | constant_time_eq(t.as_bytes(), token.as_bytes())
+            });
+            async move { found.then_some(token) }
+        })
+    }
+}
+
+impl<I: Clone + Send + Sync + 'static> Handler<&mut Ctx> for BearerAuthMiddleware<I> {
+    type Output = Result<(), StatusError>;
+
+    async fn call(&self, c: &mut Ctx) -> Self::Output {
+        let token = match authorization(c, "Bearer") {
+            Some(Ok(token)) if !token.contains(char::is_whitespace) => token.to_string(),
+            None => {
+                c.res
+                    .header((header::WWW_AUTHENTICATE, self.challenge(None)));
+                return Err(StatusError::unauthorized());
+            }
+            _ => {
+                let error = ("invalid_request", "Malformed Authorization header");
+                c.res
+                    .header((header::WWW_AUTHENTICATE, self.challenge(Some(error))));
+                return Err(StatusError::bad_request().brief(error.1));
+            }
+        };
+
+        let Some(identity) = (self.validator)(token).await else {
+            let error = ("invalid_token", "The access token is invalid or expired");
+            c.res
+                .header((header::WWW_AUTHENTICATE, self.challenge(Some(error))));
+            return Err(StatusError::unauthorized().brief(error.1));
+        };
+
+        c.req.extensions_mut().insert(Identity(identity));
+        c.next().await;
+        Ok(())
+    }
+}
+
+impl Request {
+    /// The identity returned by the validator of `BasicAuthMiddleware` or `BearerAuthMiddleware`.
+    pub fn identity<I: Clone + Send + Sync + 'static>(&self) -> Option<&I> {
+        self.extensions().get::<Identity<I>>().map(|i| &i.0)
+    }
+}
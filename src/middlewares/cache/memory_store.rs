@@ -0,0 +1,125 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use super::{CacheStore, CachedResponse};
+
+/// In-memory store that evicts the least recently used responses once it holds more
+/// than `capacity` responses or `max_bytes` of bodies.
+///
+/// Cloning shares the store.
+#[derive(Clone, Debug)]
+pub struct MemoryStore {
+    inner: Arc<Mutex<Lru>>,
+}
+
+#[derive(Debug)]
+struct Lru {
+    entries: HashMap<String, Entry>,
+    /// Last use of every key, oldest first
+    order: BTreeMap<u64, String>,
+    tick: u64,
+    bytes: usize,
+    capacity: usize,
+    max_bytes: usize,
+}
+
+#[derive(Debug)]
+struct Entry {
+    response: CachedResponse,
+    used: u64,
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryStore {
+    /// Creates a store holding up to 1024 responses and 64MB of bodies.
+    pub fn new() -> Self {
+        Self::with_capacity(1024, 64 * 1024 * 1024)
+    }
+
+    pub fn with_capacity(capacity: usize, max_bytes: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Lru {
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+                tick: 0,
+                bytes: 0,
+                capacity,
+                max_bytes,
+            })),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Lru> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Lru {
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        self.order.remove(&entry.used);
+        self.bytes -= entry.response.body.len();
+        Some(entry)
+    }
+
+    fn evict(&mut self) {
+        while self.entries.len() > self.capacity || self.bytes > self.max_bytes {
+            let Some((_, key)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&key) {
+                self.bytes -= entry.response.body.len();
+            }
+        }
+    }
+}
+
+impl CacheStore for MemoryStore {
+    async fn get(&self, key: &str) -> Option<CachedResponse> {
+        let mut lru = self.lock();
+        lru.tick += 1;
+        let tick = lru.tick;
+
+        let entry = lru.entries.get_mut(key)?;
+        if !entry.response.is_usable() {
+            lru.remove(key);
+            return None;
+        }
+        let used = std::mem::replace(&mut entry.used, tick);
+        let response = entry.response.clone();
+        lru.order.remove(&used);
+        lru.order.insert(tick, key.to_string());
+        Some(response)
+    }
+
+    async fn set(&self, key: &str, response: CachedResponse) {
+        let mut lru = self.lock();
+        lru.remove(key);
+        lru.tick += 1;
+        let used = lru.tick;
+        lru.bytes += response.body.len();
+        lru.entries
+            .insert(key.to_string(), Entry { response, used });
+        lru.order.insert(used, key.to_string());
+        lru.evict();
+    }
+
+    async fn remove(&self, key: &str) {
+        self.lock().remove(key);
+    }
+}
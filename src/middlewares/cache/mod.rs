@@ -0,0 +1,360 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, SystemTime},
+};
+
+use http::{HeaderName, HeaderValue, Method, StatusCode, header};
+use tokio::sync::watch;
+
+mod memory_store;
+mod store;
+
+pub use memory_store::MemoryStore;
+pub use store::{CacheStore, CachedResponse};
+
+use crate::{ctx::Ctx, handler::Handler, response::Response};
+
+/// Statuses that can be cached without explicit freshness, per RFC 9111.
+const CACHEABLE_STATUSES: [StatusCode; 11] = [
+    StatusCode::OK,
+    StatusCode::NON_AUTHORITATIVE_INFORMATION,
+    StatusCode::NO_CONTENT,
+    StatusCode::MULTIPLE_CHOICES,
+    StatusCode::MOVED_PERMANENTLY,
+    StatusCode::PERMANENT_REDIRECT,
+    StatusCode::NOT_FOUND,
+    StatusCode::METHOD_NOT_ALLOWED,
+    StatusCode::GONE,
+    StatusCode::URI_TOO_LONG,
+    StatusCode::NOT_IMPLEMENTED,
+];
+
+type Inflight = Arc<Mutex<HashMap<String, watch::Receiver<()>>>>;
+
+#[derive(Clone, Copy, Debug)]
+struct Policy {
+    ttl: Duration,
+    stale_while_revalidate: Duration,
+    max_body_size: usize,
+    cache_cookies: bool,
+}
+
+/// Caches full responses to `GET` requests, and serves `HEAD` requests from them.
+///
+/// Responses are keyed by path, query and the request headers added with
+/// [`CacheMiddleware::vary`]. Handlers control caching with `Cache-Control`: `no-store`,
+/// `no-cache` and `private` skip the cache, `max-age`/`s-maxage` and `stale-while-revalidate`
+/// override the defaults. Responses that set cookies, stream their body, or `Vary` on headers
+/// not added with [`CacheMiddleware::vary`] aren't cached, and requests with an
+/// `Authorization` or `Cookie` header bypass the cache.
+///
+/// Stale responses are served while a fresh one is generated in the background, and
+/// concurrent misses for the same key wait for the first one instead of all running the
+/// handler. Replayed responses carry an `Age` header.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use maw::{prelude::*, CacheMiddleware};
+///
+/// let router = Router::new()
+///     .middleware(CacheMiddleware::new(Duration::from_secs(60)))
+///     .get("/", async |_: &mut Ctx| "expensive");
+/// ```
+pub struct CacheMiddleware<S: CacheStore = MemoryStore> {
+    store: Arc<S>,
+    policy: Policy,
+    vary: Vec<HeaderName>,
+    inflight: Inflight,
+}
+
+impl<S: CacheStore> Clone for CacheMiddleware<S> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            policy: self.policy,
+            vary: self.vary.clone(),
+            inflight: self.inflight.clone(),
+        }
+    }
+}
+
+impl<S: CacheStore + std::fmt::Debug> std::fmt::Debug for CacheMiddleware<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CacheMiddleware")
+            .field("store", &self.store)
+            .field("ttl", &self.policy.ttl)
+            .field(
+                "stale_while_revalidate",
+                &self.policy.stale_while_revalidate,
+            )
+            .field("vary", &self.vary)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CacheMiddleware {
+    /// Cache responses for `ttl`, in memory.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            store: Arc::new(MemoryStore::new()),
+            policy: Policy {
+                ttl,
+                stale_while_revalidate: Duration::ZERO,
+                max_body_size: 1024 * 1024,
+                cache_cookies: false,
+            },
+            vary: Vec::new(),
+            inflight: Default::default(),
+        }
+    }
+}
+
+impl<S: CacheStore> CacheMiddleware<S> {
+    pub fn store<T: CacheStore>(self, store: T) -> CacheMiddleware<T> {
+        CacheMiddleware {
+            store: Arc::new(store),
+            policy: self.policy,
+            vary: self.vary,
+            inflight: self.inflight,
+        }
+    }
+
+    /// How long stale responses are still served after `ttl`, while a fresh one is
+    /// generated in the background.
+    ///
+    /// Default: 0
+    pub fn stale_while_revalidate(mut self, duration: Duration) -> Self {
+        self.policy.stale_while_revalidate = duration;
+        self
+    }
+
+    /// Cache a separate response for each value of this request header,
+    /// e.g. `Accept-Encoding` when behind `CompressionMiddleware`.
+    pub fn vary(mut self, header: HeaderName) -> Self {
+        self.vary.push(header);
+        self
+    }
+
+    /// Bigger responses aren't cached.
+    ///
+    /// Default: 1MB
+    pub fn max_body_size(mut self, size: usize) -> Self {
+        self.policy.max_body_size = size;
+        self
+    }
+
+    /// Cache requests that carry a `Cookie` header, for apps whose responses don't depend
+    /// on cookies. Every user is served the same cached response, and responses that set
+    /// cookies are still never cached.
+    ///
+    /// Default: false
+    pub fn cache_cookies(mut self, enabled: bool) -> Self {
+        self.policy.cache_cookies = enabled;
+        self
+    }
+
+    /// Whether the response to this request may depend on who sent it.
+    fn is_personalised(c: &Ctx) -> bool {
+        c.req.headers().contains_key(header::AUTHORIZATION)
+            || c.req.headers().contains_key(header::COOKIE)
+    }
+
+    fn key(&self, c: &Ctx) -> String {
+        let uri = c.req.uri();
+        let mut key = uri
+            .path_and_query()
+            .map_or_else(|| uri.path().to_string(), |pq| pq.to_string());
+        for name in &self.vary {
+            key.push('\n');
+            key.push_str(name.as_str());
+            key.push(':');
+            for value in c.req.headers().get_all(name) {
+                key.push_str(value.to_str().unwrap_or_default());
+                key.push(',');
+            }
+        }
+        key
+    }
+
+    /// Whether the response may be stored, and for how long.
+    fn policy_for(&self, res: &Response) -> Option<Policy> {
+        if !CACHEABLE_STATUSES.contains(&res.inner.status())
+            || res.headers().contains_key(header::SET_COOKIE)
+        {
+            return None;
+        }
+
+        for value in res.headers().get_all(header::VARY) {
+            for field in value.to_str().ok()?.split(',').map(str::trim) {
+                if field.is_empty() {
+                    continue;
+                }
+                if field == "*"
+                    || !self
+                        .vary
+                        .iter()
+                        .any(|v| v.as_str().eq_ignore_ascii_case(field))
+                {
+                    return None;
+                }
+            }
+        }
+
+        let mut policy = self.policy;
+        let mut max_age = None;
+        for value in res.headers().get_all(header::CACHE_CONTROL) {
+            for directive in value.to_str().ok()?.split(',') {
+                let (name, arg) = match directive.split_once('=') {
+                    Some((name, arg)) => (name.trim(), Some(arg.trim().trim_matches('"'))),
+                    None => (directive.trim(), None),
+                };
+                let secs = || arg.and_then(|a| a.parse().ok()).map(Duration::from_secs);
+                match name.to_ascii_lowercase().as_str() {
+                    "no-store" | "no-cache" | "private" => return None,
+                    // s-maxage is meant for shared caches like this one, and wins over max-age
+                    "s-maxage" => max_age = secs(),
+                    "max-age" => policy.ttl = secs()?,
+                    "stale-while-revalidate" => policy.stale_while_revalidate = secs()?,
+                    _ => {}
+                }
+            }
+        }
+        if let Some(max_age) = max_age {
+            policy.ttl = max_age;
+        }
+
+        (!policy.ttl.is_zero()).then_some(policy)
+    }
+
+    /// Takes what the handlers produced, if it can be cached.
+    async fn capture(&self, c: &mut Ctx) -> Option<CachedResponse> {
+        let policy = self.policy_for(&c.res)?;
+        if c.res.body().is_stream() {
+            return None;
+        }
+        // Already in memory, so this can't fail or lose the body
+        let body = c.res.read_body(usize::MAX).await.ok()?;
+        if body.len() > policy.max_body_size {
+            return None;
+        }
+        Some(CachedResponse {
+            status: c.res.inner.status(),
+            headers: c.res.headers().clone(),
+            body,
+            created: SystemTime::now(),
+            ttl: policy.ttl,
+            stale_while_revalidate: policy.stale_while_revalidate,
+        })
+    }
+
+    /// Marks `key` as being generated, or returns a receiver that resolves once the
+    /// request already generating it is done.
+    fn lead(&self, key: &str) -> Result<InflightGuard, watch::Receiver<()>> {
+        let mut inflight = self.inflight.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(rx) = inflight.get(key) {
+            return Err(rx.clone());
+        }
+        let (tx, rx) = watch::channel(());
+        inflight.insert(key.to_string(), rx);
+        Ok(InflightGuard {
+            inflight: self.inflight.clone(),
+            key: key.to_string(),
+            _tx: tx,
+        })
+    }
+
+    fn revalidate_in_background(&self, c: &Ctx, key: String) {
+        let Ok(guard) = self.lead(&key) else {
+            return;
+        };
+        let mut fork = c.fork();
+        let this = self.clone();
+        tokio::spawn(async move {
+            fork.next().await;
+            if let Some(response) = this.capture(&mut fork).await {
+                this.store.set(&key, response).await;
+            }
+            drop(guard);
+        });
+    }
+}
+
+/// Removes the key from the in-flight map when generating it is done (or was cancelled),
+/// waking the requests waiting for it.
+struct InflightGuard {
+    inflight: Inflight,
+    key: String,
+    _tx: watch::Sender<()>,
+}
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        self.inflight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.key);
+    }
+}
+
+fn replay(c: &mut Ctx, cached: &CachedResponse) {
+    c.res.status(cached.status);
+    *c.res.headers_mut() = cached.headers.clone();
+    c.res
+        .header((header::AGE, HeaderValue::from(cached.age().as_secs())));
+    c.res.send(cached.body.clone());
+}
+
+impl<S: CacheStore> Handler<&mut Ctx> for CacheMiddleware<S> {
+    type Output = ();
+
+    async fn call(&self, c: &mut Ctx) -> Self::Output {
+        let method = c.req.method();
+        let personalised = Self::is_personalised(c);
+        if (method != Method::GET && method != Method::HEAD)
+            || c.req.headers().contains_key(header::AUTHORIZATION)
+            || (personalised && !self.policy.cache_cookies)
+        {
+            return c.next().await;
+        }
+
+        let key = self.key(c);
+        if let Some(cached) = self.store.get(&key).await
+            && cached.is_usable()
+        {
+            if cached.is_fresh() {
+                return replay(c, &cached);
+            }
+            // A personalised request never refreshes the shared entry, it's left to an
+            // anonymous one
+            if !personalised {
+                self.revalidate_in_background(c, key);
+                return replay(c, &cached);
+            }
+            return c.next().await;
+        }
+
+        // Whatever a HEAD handler produced isn't the body a GET would get
+        if c.req.is_head() {
+            return c.next().await;
+        }
+
+        match self.lead(&key) {
+            Ok(guard) => {
+                c.next().await;
+                if let Some(response) = self.capture(c).await {
+                    self.store.set(&key, response).await;
+                }
+                drop(guard);
+            }
+            Err(mut rx) => {
+                // Resolves once the leader is done, its response is in the store if cacheable
+                let _ = rx.changed().await;
+                match self.store.get(&key).await {
+                    Some(cached) if cached.is_usable() => replay(c, &cached),
+                    _ => c.next().await,
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use std::time::{Duration, SystemTime};
+
+use bytes::Bytes;
+use http::{HeaderMap, StatusCode};
+
+/// A response stored by [`CacheMiddleware`](super::CacheMiddleware).
+#[derive(Clone, Debug)]
+pub struct CachedResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    /// When the response was generated
+    pub created: SystemTime,
+    /// How long it's fresh for
+    pub ttl: Duration,
+    /// How long it may still be served after `ttl`, while it's regenerated in the background
+    pub stale_while_revalidate: Duration,
+}
+
+impl CachedResponse {
+    pub fn age(&self) -> Duration {
+        self.created.elapsed().unwrap_or_default()
+    }
+
+    pub fn is_fresh(&self) -> bool {
+        self.age() < self.ttl
+    }
+
+    /// Whether it can be served at all, fresh or stale.
+    pub fn is_usable(&self) -> bool {
+        self.age() < self.ttl + self.stale_while_revalidate
+    }
+}
+
+pub trait CacheStore: Send + Sync + 'static {
+    fn get(&self, key: &str) -> impl Future<Output = Option<CachedResponse>> + Send;
+
+    /// Store `response` under `key`, replacing what was there.
+    ///
+    /// It can be dropped once it's no longer usable, see [`CachedResponse::is_usable`].
+    fn set(&self, key: &str, response: CachedResponse) -> impl Future<Output = ()> + Send;
+
+    fn remove(&self, key: &str) -> impl Future<Output = ()> + Send;
+}
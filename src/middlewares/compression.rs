@@ -0,0 +1,373 @@
+use std::{
+    io::{self, Write},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use http::{HeaderValue, StatusCode, header};
+use http_body::{Body as HttpBodyTrait, Frame, SizeHint};
+
+use crate::{
+    ctx::Ctx,
+    handler::Handler,
+    negotiate,
+    response::{BoxError, HttpBody},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Zstd,
+    Gzip,
+}
+
+impl Encoding {
+    fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+        }
+    }
+}
+
+/// Compresses responses based on the request's `Accept-Encoding` header.
+///
+/// Both buffered and streaming bodies are compressed. Server-sent events are flushed
+/// after every event so they still reach the client right away.
+#[derive(Clone, Debug)]
+pub struct CompressionMiddleware {
+    /// Supported encodings, in order of preference when the client has no preference.
+    encodings: Vec<Encoding>,
+    /// Bodies smaller than this are sent as is.
+    ///
+    /// Default: 1024
+    min_size: usize,
+    /// Content types that are never compressed, matched by prefix.
+    skip_content_types: Vec<String>,
+}
+
+impl Default for CompressionMiddleware {
+    fn default() -> Self {
+        Self {
+            encodings: vec![Encoding::Brotli, Encoding::Zstd, Encoding::Gzip],
+            min_size: 1024,
+            skip_content_types: [
+                "image/",
+                "audio/",
+                "video/",
+                "font/woff",
+                "application/zip",
+                "application/gzip",
+                "application/x-gzip",
+                "application/zstd",
+                "application/x-7z-compressed",
+                "application/x-rar-compressed",
+                "application/x-bzip2",
+                "application/octet-stream",
+                "application/wasm",
+                "application/pdf",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        }
+    }
+}
+
+impl CompressionMiddleware {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the supported encodings, in order of preference.
+    ///
+    /// Default: `[Brotli, Zstd, Gzip]`
+    pub fn encodings(mut self, encodings: impl IntoIterator<Item = Encoding>) -> Self {
+        self.encodings = encodings.into_iter().collect();
+        self
+    }
+
+    /// Set the minimum body size to compress, in bytes.
+    ///
+    /// Default: 1024
+    pub fn min_size(mut self, bytes: usize) -> Self {
+        self.min_size = bytes;
+        self
+    }
+
+    /// Never compress responses whose content type starts with `prefix`.
+    ///
+    /// `image/svg+xml` is always compressed, as it's text.
+    pub fn skip_content_type(mut self, prefix: impl Into<String>) -> Self {
+        self.skip_content_types.push(prefix.into());
+        self
+    }
+
+    fn is_compressible(&self, c: &Ctx) -> bool {
+        let res = &c.res.inner;
+        let status = res.status();
+        // `HEAD` handlers may leave the body out and only set the length a `GET` would get
+        let body_left_out = head_without_body(c);
+        if status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+            || status == StatusCode::PARTIAL_CONTENT
+            || (matches!(res.body(), HttpBody::Empty) && !body_left_out)
+            || res.headers().contains_key(header::CONTENT_ENCODING)
+        {
+            return false;
+        }
+
+        let no_transform = res
+            .headers()
+            .get(header::CACHE_CONTROL)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.to_ascii_lowercase().contains("no-transform"));
+        if no_transform {
+            return false;
+        }
+
+        if let Some(content_type) = res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+        {
+            let content_type = content_type.to_ascii_lowercase();
+            let skipped = self
+                .skip_content_types
+                .iter()
+                .any(|p| content_type.starts_with(p.as_str()));
+            if skipped && !content_type.starts_with("image/svg+xml") {
+                return false;
+            }
+        }
+
+        let content_length = res
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+        let size = match res.body() {
+            _ if body_left_out => content_length,
+            HttpBody::Stream(_) => content_length,
+            body => body.size_hint().exact(),
+        };
+        size.is_none_or(|size| size >= self.min_size as u64)
+    }
+}
+
+/// A `HEAD` response without a body, but with the `Content-Length` of the `GET` one.
+fn head_without_body(c: &Ctx) -> bool {
+    c.req.is_head()
+        && matches!(c.res.inner.body(), HttpBody::Empty)
+        && c.res.headers().contains_key(header::CONTENT_LENGTH)
+}
+
+impl Handler<&mut Ctx> for CompressionMiddleware {
+    type Output = ();
+
+    async fn call(&self, c: &mut Ctx) -> Self::Output {
+        c.next().await;
+
+        if !self.is_compressible(c) {
+            return;
+        }
+
+        c.res.vary("Accept-Encoding");
+
+        let Some(encoding) = c
+            .req
+            .header(header::ACCEPT_ENCODING)
+            // Ties go to the server's preference
+            .and_then(|v| negotiate::encoding(v, self.encodings.iter().map(|&e| (e, e.as_str()))))
+        else {
+            return;
+        };
+
+        let flush = c
+            .res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/event-stream"));
+
+        // The compressed length of a left out `HEAD` body isn't known, so it goes out like a stream
+        let body_left_out = head_without_body(c);
+        let body = match c.res.take_body() {
+            body if body.is_stream() || body_left_out => HttpBody::from_body(CompressedBody {
+                inner: body,
+                encoder: Some(Encoder::new(encoding)),
+                flush,
+                trailers: None,
+            }),
+            body => {
+                let bytes = match body.collect(usize::MAX).await {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        tracing::error!("failed to read response body for compression: {e}");
+                        c.res.send_status(StatusCode::INTERNAL_SERVER_ERROR);
+                        return;
+                    }
+                };
+                let mut encoder = Encoder::new(encoding);
+                let compressed = encoder.write(&bytes, false).and_then(|mut out| {
+                    out.extend_from_slice(&encoder.finish()?);
+                    Ok(out)
+                });
+                match compressed {
+                    Ok(out) => HttpBody::full(out.into()),
+                    Err(e) => {
+                        tracing::error!("failed to compress response body: {e}");
+                        c.res.set_body(HttpBody::full(bytes));
+                        return;
+                    }
+                }
+            }
+        };
+
+        c.res.set_body(body);
+
+        let headers = c.res.headers_mut();
+        headers.insert(
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static(encoding.as_str()),
+        );
+        headers.remove(header::ACCEPT_RANGES);
+        // The compressed body is a different representation, so a strong validator no longer holds
+        if let Some(etag) = headers.get(header::ETAG).and_then(|v| v.to_str().ok())
+            && !etag.starts_with("W/")
+            && let Ok(weak) = HeaderValue::from_str(&["W/", etag].concat())
+        {
+            headers.insert(header::ETAG, weak);
+        }
+    }
+}
+
+enum Encoder {
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+    Finished,
+}
+
+impl Encoder {
+    fn new(encoding: Encoding) -> Self {
+        match encoding {
+            Encoding::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            )),
+            // Quality 11 is way too slow for dynamic content
+            Encoding::Brotli => Encoder::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                4096,
+                5,
+                22,
+            ))),
+            Encoding::Zstd => match zstd::stream::write::Encoder::new(Vec::new(), 3) {
+                Ok(encoder) => Encoder::Zstd(encoder),
+                Err(e) => {
+                    tracing::error!("failed to create zstd encoder: {e}");
+                    Encoder::Finished
+                }
+            },
+        }
+    }
+
+    /// Compresses `data` and returns whatever output the encoder produced so far.
+    fn write(&mut self, data: &[u8], flush: bool) -> io::Result<Vec<u8>> {
+        let out = match self {
+            Encoder::Gzip(e) => {
+                e.write_all(data)?;
+                if flush {
+                    e.flush()?;
+                }
+                e.get_mut()
+            }
+            Encoder::Brotli(e) => {
+                e.write_all(data)?;
+                if flush {
+                    e.flush()?;
+                }
+                e.get_mut()
+            }
+            Encoder::Zstd(e) => {
+                e.write_all(data)?;
+                if flush {
+                    e.flush()?;
+                }
+                e.get_mut()
+            }
+            Encoder::Finished => return Err(io::Error::other("encoder already finished")),
+        };
+        Ok(std::mem::take(out))
+    }
+
+    fn finish(&mut self) -> io::Result<Vec<u8>> {
+        match std::mem::replace(self, Encoder::Finished) {
+            Encoder::Gzip(e) => e.finish(),
+            Encoder::Brotli(e) => Ok(e.into_inner()),
+            Encoder::Zstd(e) => e.finish(),
+            Encoder::Finished => Ok(Vec::new()),
+        }
+    }
+}
+
+struct CompressedBody {
+    inner: HttpBody,
+    encoder: Option<Encoder>,
+    flush: bool,
+    trailers: Option<Frame<Bytes>>,
+}
+
+impl HttpBodyTrait for CompressedBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        loop {
+            let Some(encoder) = this.encoder.as_mut() else {
+                return Poll::Ready(this.trailers.take().map(Ok));
+            };
+
+            match Pin::new(&mut this.inner).poll_frame(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(Some(Ok(frame))) => match frame.into_data() {
+                    Ok(data) => {
+                        let out = encoder.write(&data, this.flush)?;
+                        if !out.is_empty() {
+                            return Poll::Ready(Some(Ok(Frame::data(out.into()))));
+                        }
+                    }
+                    // Trailers end the body, finish the compressed stream before sending them
+                    Err(trailers) => {
+                        this.trailers = Some(trailers);
+                        let out = this
+                            .encoder
+                            .take()
+                            .map_or(Ok(Vec::new()), |mut e| e.finish())?;
+                        return Poll::Ready(Some(Ok(Frame::data(out.into()))));
+                    }
+                },
+                Poll::Ready(None) => {
+                    let out = this
+                        .encoder
+                        .take()
+                        .map_or(Ok(Vec::new()), |mut e| e.finish())?;
+                    return Poll::Ready(Some(Ok(Frame::data(out.into()))));
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::new()
+    }
+}
@@ -29,6 +29,11 @@ impl CookieStore {
         self.jar.remove(name);
     }
 
+    /// Get the value as the client sent it, for cookies not set through this store.
+    pub fn get_raw(&self, name: &str) -> Option<&str> {
+        self.jar.get(name).map(|c| c.value())
+    }
+
     pub fn get<T: DeserializeOwned>(&self, name: &str) -> Result<T, CookieError> {
         let cookie = self
             .jar
@@ -0,0 +1,290 @@
+use std::{sync::Arc, time::Duration};
+
+use http::{HeaderName, HeaderValue, Method, StatusCode, header};
+
+use crate::{app::App, ctx::Ctx, handler::Handler, router};
+
+#[derive(Clone)]
+enum AllowOrigin {
+    Any,
+    List(Vec<OriginPattern>),
+    Predicate(Arc<dyn Fn(&str) -> bool + Send + Sync>),
+}
+
+#[derive(Clone, Debug)]
+enum OriginPattern {
+    Exact(String),
+    /// `https://*.example.com`, split around the `*`
+    Wildcard(String, String),
+}
+
+impl OriginPattern {
+    fn new(pattern: &str) -> Self {
+        match pattern.split_once('*') {
+            Some((prefix, suffix)) => {
+                OriginPattern::Wildcard(prefix.to_ascii_lowercase(), suffix.to_ascii_lowercase())
+            }
+            None => OriginPattern::Exact(pattern.trim_end_matches('/').to_ascii_lowercase()),
+        }
+    }
+
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            OriginPattern::Exact(o) => o.eq_ignore_ascii_case(origin),
+            OriginPattern::Wildcard(prefix, suffix) => {
+                let origin = origin.to_ascii_lowercase();
+                origin.len() > prefix.len() + suffix.len()
+                    && origin.starts_with(prefix.as_str())
+                    && origin.ends_with(suffix.as_str())
+                    && !origin[prefix.len()..origin.len() - suffix.len()].contains('/')
+            }
+        }
+    }
+}
+
+/// Handles CORS for every route it's added to.
+///
+/// Preflight requests are answered directly, without running the rest of the chain.
+/// Paths using this middleware without an `OPTIONS` handler answer `OPTIONS`
+/// automatically, through the middlewares of the method being preflighted, so there is
+/// no need to register one.
+///
+/// Should be added before any middleware that may respond early, so error
+/// responses get the CORS headers too.
+#[derive(Clone)]
+pub struct CorsMiddleware {
+    origin: AllowOrigin,
+    methods: Vec<Method>,
+    /// `None` mirrors the request's `Access-Control-Request-Headers`
+    headers: Option<Vec<HeaderName>>,
+    expose_headers: Vec<HeaderName>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Default for CorsMiddleware {
+    fn default() -> Self {
+        Self {
+            origin: AllowOrigin::List(Vec::new()),
+            methods: vec![
+                Method::GET,
+                Method::HEAD,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+            ],
+            headers: None,
+            expose_headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for CorsMiddleware {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let origin: &dyn std::fmt::Debug = match &self.origin {
+            AllowOrigin::Any => &"*",
+            AllowOrigin::List(list) => list,
+            AllowOrigin::Predicate(_) => &"<fn>",
+        };
+        f.debug_struct("CorsMiddleware")
+            .field("origin", origin)
+            .field("methods", &self.methods)
+            .field("headers", &self.headers)
+            .field("expose_headers", &self.expose_headers)
+            .field("credentials", &self.credentials)
+            .field("max_age", &self.max_age)
+            .finish()
+    }
+}
+
+impl CorsMiddleware {
+    /// Creates a middleware that allows no origin, add some with the `allow_*` methods.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow every origin.
+    ///
+    /// With credentials enabled, the request origin is echoed back instead of `*`.
+    pub fn allow_any_origin(mut self) -> Self {
+        self.origin = AllowOrigin::Any;
+        self
+    }
+
+    /// Allow an origin, e.g. `https://example.com`, or all subdomains with `https://*.example.com`.
+    pub fn allow_origin(mut self, origin: impl AsRef<str>) -> Self {
+        let pattern = OriginPattern::new(origin.as_ref());
+        match &mut self.origin {
+            AllowOrigin::List(list) => list.push(pattern),
+            origin => *origin = AllowOrigin::List(vec![pattern]),
+        }
+        self
+    }
+
+    /// Allow the origins for which `f` returns true.
+    pub fn allow_origin_fn<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.origin = AllowOrigin::Predicate(Arc::new(f));
+        self
+    }
+
+    /// Default: GET, HEAD, POST, PUT, PATCH, DELETE
+    pub fn allow_methods(mut self, methods: impl IntoIterator<Item = Method>) -> Self {
+        self.methods = methods.into_iter().collect();
+        self
+    }
+
+    /// Default: whatever the preflight request asks for
+    pub fn allow_headers(mut self, headers: impl IntoIterator<Item = HeaderName>) -> Self {
+        self.headers = Some(headers.into_iter().collect());
+        self
+    }
+
+    /// Response headers that scripts are allowed to read.
+    pub fn expose_headers(mut self, headers: impl IntoIterator<Item = HeaderName>) -> Self {
+        self.expose_headers = headers.into_iter().collect();
+        self
+    }
+
+    /// Default: false
+    pub fn allow_credentials(mut self, allow: bool) -> Self {
+        self.credentials = allow;
+        self
+    }
+
+    /// How long browsers may cache preflight responses.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    fn is_allowed(&self, origin: &str) -> bool {
+        match &self.origin {
+            AllowOrigin::Any => true,
+            AllowOrigin::List(list) => list.iter().any(|p| p.matches(origin)),
+            AllowOrigin::Predicate(f) => f(origin),
+        }
+    }
+
+    /// Whether the allowed origin header changes with the request origin.
+    fn varies_by_origin(&self) -> bool {
+        !matches!(self.origin, AllowOrigin::Any) || self.credentials
+    }
+
+    fn set_origin_headers(&self, c: &mut Ctx, origin: &HeaderValue) {
+        let headers = c.res.headers_mut();
+        if self.varies_by_origin() {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+        } else {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_ORIGIN,
+                HeaderValue::from_static("*"),
+            );
+        }
+        if self.credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+
+    fn preflight(&self, c: &mut Ctx, origin: &HeaderValue) {
+        c.res.vary("Origin");
+        c.res.vary("Access-Control-Request-Method");
+        c.res.vary("Access-Control-Request-Headers");
+
+        let allowed_method = c
+            .req
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|m| Method::from_bytes(m.as_bytes()).ok())
+            .is_some_and(|m| self.methods.contains(&m));
+        if !allowed_method {
+            c.res.status(StatusCode::NO_CONTENT);
+            return;
+        }
+
+        self.set_origin_headers(c, origin);
+
+        let methods = join(self.methods.iter().map(Method::as_str));
+        let allow_headers = match &self.headers {
+            Some(headers) => Some(join(headers.iter().map(HeaderName::as_str))),
+            None => c
+                .req
+                .header(header::ACCESS_CONTROL_REQUEST_HEADERS)
+                .map(str::to_string),
+        };
+
+        c.res
+            .header((header::ACCESS_CONTROL_ALLOW_METHODS, methods))
+            .status(StatusCode::NO_CONTENT);
+        if let Some(allow_headers) = allow_headers.filter(|h| !h.is_empty()) {
+            c.res
+                .header((header::ACCESS_CONTROL_ALLOW_HEADERS, allow_headers));
+        }
+        if let Some(max_age) = self.max_age {
+            c.res.header((
+                header::ACCESS_CONTROL_MAX_AGE,
+                max_age.as_secs().to_string(),
+            ));
+        }
+    }
+}
+
+fn join<'a>(items: impl Iterator<Item = &'a str>) -> String {
+    items.collect::<Vec<_>>().join(", ")
+}
+
+impl Handler<&mut Ctx> for CorsMiddleware {
+    type Output = ();
+
+    fn on_app_listen_mut(&self, app: &mut App) {
+        router::add_auto_options::<Self>(app);
+    }
+
+    async fn call(&self, c: &mut Ctx) -> Self::Output {
+        let origin = c
+            .req
+            .headers()
+            .get(header::ORIGIN)
+            .filter(|o| o.to_str().is_ok_and(|o| self.is_allowed(o)))
+            .cloned();
+
+        let is_preflight = c.req.method() == Method::OPTIONS
+            && c.req
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+
+        if is_preflight {
+            match origin {
+                Some(origin) => self.preflight(c, &origin),
+                // Not ours to answer, let the browser block it
+                None => {
+                    c.res.vary("Origin");
+                    c.res.status(StatusCode::NO_CONTENT);
+                }
+            }
+            return;
+        }
+
+        c.next().await;
+
+        if self.varies_by_origin() {
+            c.res.vary("Origin");
+        }
+
+        if let Some(origin) = origin {
+            self.set_origin_headers(c, &origin);
+            if !self.expose_headers.is_empty() {
+                let expose = join(self.expose_headers.iter().map(HeaderName::as_str));
+                c.res
+                    .header((header::ACCESS_CONTROL_EXPOSE_HEADERS, expose));
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::{fmt, future::Future, pin::Pin, sync::Arc, time::SystemTime};
+
+use http::{HeaderValue, Method, StatusCode, header};
+
+use crate::{
+    ctx::Ctx,
+    handler::Handler,
+    prelude::StatusError,
+    response::{HttpBody, Response},
+};
+
+/// The current `ETag` and `Last-Modified` of a resource, `None` for both when it doesn't exist.
+type Validators = (Option<String>, Option<SystemTime>);
+
+/// Object safe form of a validators function, see [`ETagMiddleware::validators`].
+trait ValidatorsFn: Send + Sync {
+    fn call<'a>(&'a self, c: &'a Ctx) -> Pin<Box<dyn Future<Output = Validators> + Send + 'a>>;
+}
+
+impl<F> ValidatorsFn for F
+where
+    F: for<'a> Handler<&'a Ctx, Output = Validators> + Send + Sync,
+{
+    fn call<'a>(&'a self, c: &'a Ctx) -> Pin<Box<dyn Future<Output = Validators> + Send + 'a>> {
+        Box::pin(Handler::call(self, c))
+    }
+}
+
+/// Adds a strong `ETag` to responses and answers conditional requests.
+///
+/// Buffered bodies are hashed, streamed ones only get an ETag if the handler set one.
+/// `GET` and `HEAD` requests whose `If-None-Match` (or `If-Modified-Since`, against the
+/// handler's `Last-Modified`) matches get a `304 Not Modified` without a body.
+///
+/// Unsafe requests carrying `If-Match`, `If-Unmodified-Since` or `If-None-Match` get a
+/// `412 Precondition Failed` before their handler runs when the client's copy is out of
+/// date, checked against the resource's current validators from
+/// [`validators`](Self::validators). Without it they are left to the handler, which can
+/// answer them with `c.req.check_preconditions(etag, last_modified)?`.
+///
+/// ```rust,ignore
+/// ETagMiddleware::new().validators(async |c: &Ctx| {
+///     let id = c.req.param_str("id");
+///     db::post_version(id).await.map_or((None, None), |v| (Some(v.etag), Some(v.updated_at)))
+/// })
+/// ```
+#[derive(Clone, Default)]
+pub struct ETagMiddleware {
+    weak: bool,
+    validators: Option<Arc<dyn ValidatorsFn>>,
+}
+
+impl fmt::Debug for ETagMiddleware {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ETagMiddleware")
+            .field("weak", &self.weak)
+            .field("validators", &self.validators.as_ref().map(|_| "<fn>"))
+            .finish()
+    }
+}
+
+impl ETagMiddleware {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Generate weak ETags (`W/"..."`), for responses that another middleware may
+    /// re-encode, like compression.
+    ///
+    /// Default: false
+    pub fn weak(mut self, weak: bool) -> Self {
+        self.weak = weak;
+        self
+    }
+
+    /// Looks up the current `ETag` and `Last-Modified` of the requested resource, for
+    /// checking the preconditions of unsafe requests. The `ETag` is compared as is, so it
+    /// must be quoted like the one sent with `GET` responses.
+    ///
+    /// Default: none, unsafe requests aren't checked
+    pub fn validators<F>(mut self, f: F) -> Self
+    where
+        F: for<'a> Handler<&'a Ctx, Output = (Option<String>, Option<SystemTime>)>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.validators = Some(Arc::new(f));
+        self
+    }
+
+    /// The ETag the handler set, or the hash of a buffered body.
+    async fn etag(&self, res: &mut Response) -> Option<String> {
+        if let Some(etag) = res.headers().get(header::ETAG) {
+            return etag.to_str().ok().map(String::from);
+        }
+        if res.body().is_stream() {
+            return None;
+        }
+        // Already in memory, so this can't fail or lose the body
+        let body = res.read_body(usize::MAX).await.ok()?;
+        Some(self.format(body.len(), hash(&body)))
+    }
+
+    fn format(&self, len: usize, hash: u64) -> String {
+        let prefix = if self.weak { "W/" } else { "" };
+        format!("{prefix}\"{len:x}-{hash:016x}\"")
+    }
+}
+
+/// 64-bit FNV-1a, fixed so ETags stay the same across builds and replicas.
+fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn last_modified(res: &Response) -> Option<SystemTime> {
+    res.headers()
+        .get(header::LAST_MODIFIED)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok())
+}
+
+impl Handler<&mut Ctx> for ETagMiddleware {
+    type Output = Result<(), StatusError>;
+
+    async fn call(&self, c: &mut Ctx) -> Self::Output {
+        let method = c.req.method().clone();
+
+        let conditional = [
+            header::IF_MATCH,
+            header::IF_UNMODIFIED_SINCE,
+            header::IF_NONE_MATCH,
+        ]
+        .iter()
+        .any(|h| c.req.headers().contains_key(h));
+        if !method.is_safe()
+            && conditional
+            && let Some(validators) = &self.validators
+        {
+            let (etag, modified) = validators.call(c).await;
+            c.req.check_preconditions(etag.as_deref(), modified)?;
+        }
+
+        c.next().await;
+
+        if (method != Method::GET && method != Method::HEAD)
+            || c.res.inner.status() != StatusCode::OK
+        {
+            return Ok(());
+        }
+
+        let Some(etag) = self.etag(&mut c.res).await else {
+            return Ok(());
+        };
+        if let Ok(value) = HeaderValue::from_str(&etag) {
+            c.res.headers_mut().insert(header::ETAG, value);
+        }
+
+        if c.req.is_fresh(Some(&etag), last_modified(&c.res)) {
+            c.res.status(StatusCode::NOT_MODIFIED);
+            c.res.set_body(HttpBody::Empty);
+            c.res.headers_mut().remove(header::CONTENT_TYPE);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+use ipnet::IpNet;
+
+use crate::{ctx::Ctx, handler::Handler, ip, prelude::StatusError};
+
+/// Allows or denies requests by the client's IP address, as returned by `c.req.ip()`.
+///
+/// Denied ranges always win. When any range is allowed, every other address is denied.
+/// Rejected requests get a 403.
+///
+/// ```rust,ignore
+/// Router::group("/admin")
+///     .middleware(IpFilterMiddleware::new().allow("10.0.0.0/8").deny("10.0.0.13"))
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct IpFilterMiddleware {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl IpFilterMiddleware {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow a CIDR range or a single address.
+    ///
+    /// Panics if `range` is neither.
+    #[track_caller]
+    pub fn allow(mut self, range: impl AsRef<str>) -> Self {
+        self.allow.push(ip::parse_net(range.as_ref()));
+        self
+    }
+
+    /// Deny a CIDR range or a single address.
+    ///
+    /// Panics if `range` is neither.
+    #[track_caller]
+    pub fn deny(mut self, range: impl AsRef<str>) -> Self {
+        self.deny.push(ip::parse_net(range.as_ref()));
+        self
+    }
+}
+
+impl Handler<&mut Ctx> for IpFilterMiddleware {
+    type Output = Result<(), StatusError>;
+
+    async fn call(&self, c: &mut Ctx) -> Self::Output {
+        let ip = c.req.ip();
+        let allowed = if ip::in_ranges(ip, &self.deny) {
+            false
+        } else {
+            self.allow.is_empty() || ip::in_ranges(ip, &self.allow)
+        };
+
+        if !allowed {
+            tracing::debug!(%ip, "request rejected by IpFilterMiddleware");
+            return Err(StatusError::forbidden());
+        }
+
+        c.next().await;
+        Ok(())
+    }
+}
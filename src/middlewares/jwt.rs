@@ -0,0 +1,330 @@
+use std::{fmt, marker::PhantomData, path::Path, sync::Arc, time::Duration};
+
+use http::header;
+pub use jsonwebtoken::{
+    Algorithm, DecodingKey,
+    jwk::{Jwk, JwkSet},
+};
+use jsonwebtoken::{Validation, errors::ErrorKind};
+use serde::de::DeserializeOwned;
+
+use crate::{challenge::quote, ctx::Ctx, handler::Handler, prelude::StatusError, request::Request};
+
+const SUPPORTED_ALGORITHMS: [Algorithm; 4] = [
+    Algorithm::HS256,
+    Algorithm::RS256,
+    Algorithm::ES256,
+    Algorithm::EdDSA,
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum JwtError {
+    #[error("Failed to read JWKS file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid JWKS file: {0}")]
+    Jwks(#[from] serde_json::Error),
+
+    #[error("Invalid key: {0}")]
+    Key(#[from] jsonwebtoken::errors::Error),
+
+    #[error("Unsupported algorithm: {0:?}")]
+    UnsupportedAlgorithm(Algorithm),
+}
+
+struct Key {
+    kid: Option<String>,
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+/// The verified claims, stored in the request extensions.
+#[derive(Clone)]
+struct Claims<T>(T);
+
+/// Verifies JWTs and stores their claims for handlers, read them with `c.req.claims::<T>()`.
+///
+/// The token is taken from the `Authorization: Bearer` header, or from a cookie if one was
+/// set with [`JwtMiddleware::cookie`] (needs `CookieMiddleware` before this).
+/// `exp` is required and `nbf` is checked when present. Failures are answered with a 401
+/// and a `WWW-Authenticate: Bearer` challenge as in RFC 6750.
+///
+/// ```rust,ignore
+/// #[derive(Clone, Deserialize)]
+/// struct User { sub: String, roles: Vec<String> }
+///
+/// JwtMiddleware::<User>::from_jwks_file("jwks.json")?
+///     .issuer("https://id.example.com")
+///     .audience("api")
+///     .cookie("access_token")
+/// ```
+pub struct JwtMiddleware<T> {
+    keys: Arc<[Key]>,
+    validation: Validation,
+    cookie: Option<String>,
+    realm: String,
+    _claims: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for JwtMiddleware<T> {
+    fn clone(&self) -> Self {
+        Self {
+            keys: self.keys.clone(),
+            validation: self.validation.clone(),
+            cookie: self.cookie.clone(),
+            realm: self.realm.clone(),
+            _claims: PhantomData,
+        }
+    }
+}
+
+impl<T> fmt::Debug for JwtMiddleware<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtMiddleware")
+            .field("keys", &self.keys.len())
+            .field("issuer", &self.validation.iss)
+            .field("audience", &self.validation.aud)
+            .field("leeway", &self.validation.leeway)
+            .field("cookie", &self.cookie)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: DeserializeOwned + Clone + Send + Sync + 'static> JwtMiddleware<T> {
+    fn with_keys(keys: Vec<Key>) -> Self {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.algorithms = SUPPORTED_ALGORITHMS.to_vec();
+        validation.validate_nbf = true;
+        validation.validate_aud = false;
+        Self {
+            keys: keys.into(),
+            validation,
+            cookie: None,
+            realm: "Restricted".to_string(),
+            _claims: PhantomData,
+        }
+    }
+
+    /// Verify tokens signed with `algorithm` using a single key.
+    pub fn new(algorithm: Algorithm, key: DecodingKey) -> Result<Self, JwtError> {
+        if !SUPPORTED_ALGORITHMS.contains(&algorithm) {
+            return Err(JwtError::UnsupportedAlgorithm(algorithm));
+        }
+        Ok(Self::with_keys(vec![Key {
+            kid: None,
+            algorithm,
+            key,
+        }]))
+    }
+
+    /// Verify HS256 tokens with a shared secret.
+    pub fn hs256(secret: impl AsRef<[u8]>) -> Self {
+        Self::with_keys(vec![Key {
+            kid: None,
+            algorithm: Algorithm::HS256,
+            key: DecodingKey::from_secret(secret.as_ref()),
+        }])
+    }
+
+    /// Verify tokens with the keys of a JWKS, picked by the token's `kid`.
+    ///
+    /// Keys with an unsupported algorithm are skipped.
+    pub fn from_jwks(jwks: &JwkSet) -> Result<Self, JwtError> {
+        let mut keys = Vec::new();
+        for jwk in &jwks.keys {
+            let Some(algorithm) = jwk_algorithm(jwk) else {
+                tracing::warn!(kid = ?jwk.common.key_id, "skipping JWK with unsupported algorithm");
+                continue;
+            };
+            keys.push(Key {
+                kid: jwk.common.key_id.clone(),
+                algorithm,
+                key: DecodingKey::from_jwk(jwk)?,
+            });
+        }
+        Ok(Self::with_keys(keys))
+    }
+
+    /// Like [`JwtMiddleware::from_jwks`], reading the JWKS from a JSON file.
+    pub fn from_jwks_file(path: impl AsRef<Path>) -> Result<Self, JwtError> {
+        let jwks: JwkSet = serde_json::from_slice(&std::fs::read(path)?)?;
+        Self::from_jwks(&jwks)
+    }
+
+    /// Require the `iss` claim to be one of the issuers added.
+    ///
+    /// Default: not checked
+    pub fn issuer(mut self, issuer: impl ToString) -> Self {
+        let issuers = self.validation.iss.get_or_insert_default();
+        issuers.insert(issuer.to_string());
+        self
+    }
+
+    /// Require the `aud` claim to contain one of the audiences added.
+    ///
+    /// Default: not checked
+    pub fn audience(mut self, audience: impl ToString) -> Self {
+        let audiences = self.validation.aud.get_or_insert_default();
+        audiences.insert(audience.to_string());
+        self.validation.validate_aud = true;
+        self
+    }
+
+    /// Clock skew allowed when checking `exp` and `nbf`.
+    ///
+    /// Default: 60 seconds
+    pub fn leeway(mut self, leeway: Duration) -> Self {
+        self.validation.leeway = leeway.as_secs();
+        self
+    }
+
+    /// Read the token from this cookie when there is no `Authorization` header.
+    ///
+    /// Default: None
+    pub fn cookie(mut self, name: impl Into<String>) -> Self {
+        self.cookie = Some(name.into());
+        self
+    }
+
+    /// Default: "Restricted"
+    pub fn realm(mut self, realm: impl Into<String>) -> Self {
+        self.realm = realm.into();
+        self
+    }
+
+    fn token<'a>(&self, c: &'a Ctx) -> Result<Option<&'a str>, StatusError> {
+        if let Some(value) = c.req.header(header::AUTHORIZATION) {
+            return match value.trim().split_once(' ') {
+                Some((scheme, token))
+                    if scheme.eq_ignore_ascii_case("Bearer") && !token.trim().is_empty() =>
+                {
+                    Ok(Some(token.trim()))
+                }
+                _ => Err(StatusError::bad_request().brief("Malformed Authorization header")),
+            };
+        }
+
+        Ok(self
+            .cookie
+            .as_ref()
+            .and_then(|name| c.cookies.get_raw(name))
+            .filter(|token| !token.is_empty()))
+    }
+
+    fn verify(&self, token: &str) -> Result<T, jsonwebtoken::errors::Error> {
+        let header = jsonwebtoken::decode_header(token)?;
+        if !SUPPORTED_ALGORITHMS.contains(&header.alg) {
+            return Err(ErrorKind::InvalidAlgorithm.into());
+        }
+
+        let mut candidates = self.keys.iter().filter(|k| k.algorithm == header.alg);
+        let mut last_err: jsonwebtoken::errors::Error = ErrorKind::InvalidSignature.into();
+        // With a kid only that key may be used, without one try every key of the algorithm
+        let keys: Vec<&Key> = match &header.kid {
+            Some(kid) if self.keys.iter().any(|k| k.kid.is_some()) => candidates
+                .find(|k| k.kid.as_deref() == Some(kid.as_str()))
+                .into_iter()
+                .collect(),
+            _ => candidates.collect(),
+        };
+
+        let mut validation = self.validation.clone();
+        validation.algorithms = vec![header.alg];
+        for key in keys {
+            match jsonwebtoken::decode::<T>(token, &key.key, &validation) {
+                Ok(data) => return Ok(data.claims),
+                Err(e) if *e.kind() == ErrorKind::InvalidSignature => last_err = e,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(last_err)
+    }
+
+    fn challenge(&self, error: Option<(&str, &str)>) -> String {
+        let mut challenge = format!("Bearer realm=\"{}\"", quote(&self.realm));
+        if let Some((error, description)) = error {
+            challenge.push_str(&format!(
+                ", error=\"{}\", error_description=\"{}\"",
+                quote(error),
+                quote(description)
+            ));
+        }
+        challenge
+    }
+}
+
+fn jwk_algorithm(jwk: &Jwk) -> Option<Algorithm> {
+    use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, KeyAlgorithm};
+
+    let algorithm = match jwk.common.key_algorithm {
+        Some(KeyAlgorithm::HS256) => Algorithm::HS256,
+        Some(KeyAlgorithm::RS256) => Algorithm::RS256,
+        Some(KeyAlgorithm::ES256) => Algorithm::ES256,
+        Some(KeyAlgorithm::EdDSA) => Algorithm::EdDSA,
+        Some(_) => return None,
+        // `alg` is optional, fall back to what the key type allows
+        None => match &jwk.algorithm {
+            AlgorithmParameters::RSA(_) => Algorithm::RS256,
+            AlgorithmParameters::EllipticCurve(p) if p.curve == EllipticCurve::P256 => {
+                Algorithm::ES256
+            }
+            AlgorithmParameters::OctetKeyPair(p) if p.curve == EllipticCurve::Ed25519 => {
+                Algorithm::EdDSA
+            }
+            AlgorithmParameters::OctetKey(_) => Algorithm::HS256,
+            _ => return None,
+        },
+    };
+    Some(algorithm)
+}
+
+impl<T: DeserializeOwned + Clone + Send + Sync + 'static> Handler<&mut Ctx> for JwtMiddleware<T> {
+    type Output = Result<(), StatusError>;
+
+    async fn call(&self, c: &mut Ctx) -> Self::Output {
+        let token = match self.token(c) {
+            Ok(Some(token)) => token,
+            Ok(None) => {
+                c.res
+                    .header((header::WWW_AUTHENTICATE, self.challenge(None)));
+                return Err(StatusError::unauthorized());
+            }
+            Err(e) => {
+                let error = ("invalid_request", e.brief.as_str());
+                c.res
+                    .header((header::WWW_AUTHENTICATE, self.challenge(Some(error))));
+                return Err(e);
+            }
+        };
+
+        let claims = match self.verify(token) {
+            Ok(claims) => claims,
+            Err(e) => {
+                let description = match e.kind() {
+                    ErrorKind::ExpiredSignature => "The access token expired",
+                    ErrorKind::ImmatureSignature => "The access token is not valid yet",
+                    ErrorKind::InvalidIssuer => "The access token has an invalid issuer",
+                    ErrorKind::InvalidAudience => "The access token has an invalid audience",
+                    _ => "The access token is invalid",
+                };
+                tracing::debug!("rejected JWT: {e}");
+                c.res.header((
+                    header::WWW_AUTHENTICATE,
+                    self.challenge(Some(("invalid_token", description))),
+                ));
+                return Err(StatusError::unauthorized().brief(description));
+            }
+        };
+
+        c.req.extensions_mut().insert(Claims(claims));
+        c.next().await;
+        Ok(())
+    }
+}
+
+impl Request {
+    /// The claims of the token verified by `JwtMiddleware`.
+    pub fn claims<T: Clone + Send + Sync + 'static>(&self) -> Option<&T> {
+        self.extensions().get::<Claims<T>>().map(|c| &c.0)
+    }
+}
@@ -1,12 +1,185 @@
-use crate::{handler::Handler, ctx::Ctx};
+use std::{
+    net::IpAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
+use http::{Version, header};
+
+use crate::{ctx::Ctx, handler::Handler};
+
+/// Everything known about a finished request, passed to the log formatter.
+#[derive(Clone, Debug)]
+pub struct LogRecord<'a> {
+    /// When the request was received
+    pub time: SystemTime,
+    pub duration: Duration,
+    pub ip: IpAddr,
+    pub method: &'a str,
+    pub path: &'a str,
+    /// The matched route pattern, e.g. `/user/{id}`
+    pub route: &'a str,
+    pub query: Option<&'a str>,
+    pub version: Version,
+    pub status: u16,
+    /// `None` for streamed bodies of unknown length
+    pub response_size: Option<u64>,
+    pub user_agent: Option<&'a str>,
+    pub referer: Option<&'a str>,
+    pub request_id: Option<&'a str>,
+}
+
+type Formatter = Arc<dyn Fn(&LogRecord) -> String + Send + Sync>;
+
+#[derive(Clone)]
+pub enum LogFormat {
+    /// `200 | 1.234ms | 127.0.0.1 |   GET   | /path`
+    Pretty,
+    /// Apache Common Log Format
+    Common,
+    /// Apache Combined Log Format, Common plus referer and user agent
+    Combined,
+    /// One JSON object per line
+    Json,
+    Custom(Formatter),
+}
+
+impl std::fmt::Debug for LogFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogFormat::Pretty => f.write_str("Pretty"),
+            LogFormat::Common => f.write_str("Common"),
+            LogFormat::Combined => f.write_str("Combined"),
+            LogFormat::Json => f.write_str("Json"),
+            LogFormat::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+/// Logs one line per request.
+///
+/// Besides the formatted message, every event carries the request as structured
+/// `tracing` fields (`status`, `method`, `path`, `duration_ms`, ...).
+#[derive(Clone, Debug)]
 pub struct LoggingMiddleware {
-    _p: (),
+    format: LogFormat,
+    slow_threshold: Option<Duration>,
+    excluded_paths: Vec<String>,
+    sample_rate: f64,
+    counter: Arc<AtomicU64>,
+}
+
+impl Default for LoggingMiddleware {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Pretty,
+            slow_threshold: None,
+            excluded_paths: Vec::new(),
+            sample_rate: 1.0,
+            counter: Arc::new(AtomicU64::new(0)),
+        }
+    }
 }
 
 impl LoggingMiddleware {
     pub fn new() -> Self {
-        Self { _p: () }
+        Self::default()
+    }
+
+    /// Default: LogFormat::Pretty
+    pub fn format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Format the message with `f`.
+    pub fn formatter<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&LogRecord) -> String + Send + Sync + 'static,
+    {
+        self.format = LogFormat::Custom(Arc::new(f));
+        self
+    }
+
+    /// Requests slower than this are logged at warn level, and are never sampled out.
+    pub fn slow_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_threshold = Some(threshold);
+        self
+    }
+
+    /// Don't log requests to `path`, e.g. health checks.
+    ///
+    /// A trailing `*` matches every path starting with the rest, e.g. `/assets/*`.
+    pub fn exclude_path(mut self, path: impl Into<String>) -> Self {
+        self.excluded_paths.push(path.into());
+        self
+    }
+
+    /// Fraction of requests to log, between 0 and 1. Requests are picked evenly,
+    /// 0.1 logs every 10th one. Server errors and slow requests are always logged.
+    ///
+    /// Default: 1.0
+    pub fn sample_rate(mut self, rate: f64) -> Self {
+        self.sample_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    fn is_excluded(&self, path: &str) -> bool {
+        self.excluded_paths
+            .iter()
+            .any(|p| match p.strip_suffix('*') {
+                Some(prefix) => path.starts_with(prefix),
+                None => path == p,
+            })
+    }
+
+    fn is_sampled(&self) -> bool {
+        if self.sample_rate >= 1.0 {
+            return true;
+        }
+        // Logs whenever n * rate crosses an integer
+        let n = self.counter.fetch_add(1, Ordering::Relaxed) as f64;
+        ((n + 1.0) * self.sample_rate).floor() > (n * self.sample_rate).floor()
+    }
+
+    fn message(&self, r: &LogRecord) -> String {
+        match &self.format {
+            LogFormat::Pretty => format!(
+                "{} | {:^10} | {} | {:^7} | {}",
+                r.status,
+                FormattedDuration(r.duration),
+                r.ip,
+                r.method,
+                r.path,
+            ),
+            LogFormat::Common => common(r),
+            LogFormat::Combined => format!(
+                "{} \"{}\" \"{}\"",
+                common(r),
+                escape(r.referer.unwrap_or("-")),
+                escape(r.user_agent.unwrap_or("-")),
+            ),
+            LogFormat::Json => serde_json::json!({
+                "time": r.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64(),
+                "status": r.status,
+                "duration_ms": r.duration.as_secs_f64() * 1000.0,
+                "ip": r.ip,
+                "method": r.method,
+                "path": r.path,
+                "route": r.route,
+                "query": r.query,
+                "version": format!("{:?}", r.version),
+                "response_size": r.response_size,
+                "user_agent": r.user_agent,
+                "referer": r.referer,
+                "request_id": r.request_id,
+            })
+            .to_string(),
+            LogFormat::Custom(f) => f(r),
+        }
     }
 }
 
@@ -27,21 +200,159 @@ impl std::fmt::Display for FormattedDuration {
     }
 }
 
+/// `127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET /path?q HTTP/1.1" 200 2326`
+fn common(r: &LogRecord) -> String {
+    let target = match r.query {
+        Some(q) => [r.path, "?", q].concat(),
+        None => r.path.to_string(),
+    };
+    let size = r
+        .response_size
+        .map_or_else(|| "-".to_string(), |s| s.to_string());
+    format!(
+        "{} - - [{}] \"{} {} {:?}\" {} {}",
+        r.ip,
+        ApacheTime(r.time),
+        r.method,
+        escape(&target),
+        r.version,
+        r.status,
+        size,
+    )
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// `10/Oct/2000:13:55:36 +0000`, always in UTC.
+struct ApacheTime(SystemTime);
+
+impl std::fmt::Display for ApacheTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Rearranges the IMF-fixdate, `Tue, 10 Oct 2000 13:55:36 GMT`
+        let date = httpdate::fmt_http_date(self.0);
+        match date.split(' ').collect::<Vec<_>>()[..] {
+            [_, day, month, year, time, _] => write!(f, "{day}/{month}/{year}:{time} +0000"),
+            _ => f.write_str(&date),
+        }
+    }
+}
+
+macro_rules! log_request {
+    ($level:ident, $r:expr, $message:expr) => {
+        tracing::$level!(
+            status = $r.status,
+            duration_ms = $r.duration.as_secs_f64() * 1000.0,
+            ip = %$r.ip,
+            method = $r.method,
+            path = $r.path,
+            route = $r.route,
+            query = $r.query,
+            version = ?$r.version,
+            response_size = $r.response_size,
+            user_agent = $r.user_agent,
+            referer = $r.referer,
+            request_id = $r.request_id,
+            "{}",
+            $message,
+        )
+    };
+}
+
 impl Handler<&mut Ctx> for LoggingMiddleware {
     type Output = ();
 
     async fn call(&self, c: &mut Ctx) -> Self::Output {
-        let time = std::time::Instant::now();
+        if self.is_excluded(c.req.uri().path()) {
+            c.next().await;
+            return;
+        }
+
+        let time = SystemTime::now();
+        let start = std::time::Instant::now();
 
         c.next().await;
 
-        tracing::info!(
-            "{} | {:^10} | {} | {:^7} | {}",
-            c.res.inner.status().as_u16(),
-            FormattedDuration(time.elapsed()),
-            c.req.ip(),
-            c.req.method().as_str(),
-            c.req.uri().path(),
-        );
+        let duration = start.elapsed();
+        let status = c.res.inner.status();
+        let is_slow = self.slow_threshold.is_some_and(|t| duration >= t);
+        if !is_slow && !status.is_server_error() && !self.is_sampled() {
+            return;
+        }
+
+        let response_size = match c.res.inner.body() {
+            crate::response::HttpBody::Stream(_) => c
+                .res
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse().ok()),
+            body => http_body::Body::size_hint(body).exact(),
+        };
+
+        #[cfg(feature = "middleware-request_id")]
+        let request_id = c.req.request_id().or_else(|| c.req.header("x-request-id"));
+        #[cfg(not(feature = "middleware-request_id"))]
+        let request_id = c.req.header("x-request-id");
+
+        let record = LogRecord {
+            time,
+            duration,
+            ip: c.req.ip(),
+            method: c.req.method().as_str(),
+            path: c.req.uri().path(),
+            route: c.req.route(),
+            query: c.req.uri().query(),
+            version: c.req.version(),
+            status: status.as_u16(),
+            response_size,
+            user_agent: c.req.header(header::USER_AGENT),
+            referer: c.req.header(header::REFERER),
+            request_id,
+        };
+        let message = self.message(&record);
+
+        if is_slow {
+            log_request!(warn, record, message);
+        } else {
+            log_request!(info, record, message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apache_time(secs: u64) -> String {
+        ApacheTime(UNIX_EPOCH + Duration::from_secs(secs)).to_string()
+    }
+
+    #[test]
+    fn apache_time_format() {
+        assert_eq!(apache_time(0), "01/Jan/1970:00:00:00 +0000");
+        assert_eq!(apache_time(971_186_136), "10/Oct/2000:13:55:36 +0000");
+    }
+
+    #[test]
+    fn apache_time_leap_days() {
+        assert_eq!(apache_time(951_782_400), "29/Feb/2000:00:00:00 +0000");
+        assert_eq!(apache_time(1_709_251_199), "29/Feb/2024:23:59:59 +0000");
+        assert_eq!(apache_time(1_709_251_200), "01/Mar/2024:00:00:00 +0000");
+        // 2100 isn't a leap year
+        assert_eq!(apache_time(4_107_542_400), "01/Mar/2100:00:00:00 +0000");
+    }
+
+    #[test]
+    fn apache_time_year_boundary() {
+        assert_eq!(apache_time(1_704_067_199), "31/Dec/2023:23:59:59 +0000");
+        assert_eq!(apache_time(1_704_067_200), "01/Jan/2024:00:00:00 +0000");
+    }
+
+    #[test]
+    fn apache_time_drops_subseconds() {
+        let time = UNIX_EPOCH + Duration::from_millis(971_186_136_999);
+        assert_eq!(ApacheTime(time).to_string(), "10/Oct/2000:13:55:36 +0000");
     }
 }
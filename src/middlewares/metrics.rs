@@ -0,0 +1,447 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    pin::Pin,
+    sync::{
+        Arc, Mutex, OnceLock, PoisonError,
+        atomic::{AtomicI64, Ordering},
+    },
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use bytes::Bytes;
+use http::{Method, header};
+use http_body::{Body as HttpBodyTrait, Frame, SizeHint};
+
+use crate::{
+    app::App,
+    ctx::Ctx,
+    handler::Handler,
+    response::{BoxError, HttpBody},
+};
+
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+const SIZE_BUCKETS: [f64; 7] = [
+    100.0,
+    1_000.0,
+    10_000.0,
+    100_000.0,
+    1_000_000.0,
+    10_000_000.0,
+    100_000_000.0,
+];
+
+/// Records Prometheus HTTP metrics for the routes it's added to.
+///
+/// Requests are labeled by route pattern (`/user/{id}`, not `/user/42`), method and status.
+/// Serve them with [`handler`](Self::handler):
+///
+/// ```rust
+/// use maw::{prelude::*, MetricsMiddleware};
+///
+/// let metrics = MetricsMiddleware::new();
+/// let router = Router::new()
+///     .get("/metrics", metrics.handler())
+///     .middleware(metrics)
+///     .get("/user/{id}", async |_: &mut Ctx| "user");
+/// ```
+#[derive(Clone, Debug)]
+pub struct MetricsMiddleware {
+    latency_buckets: Vec<f64>,
+    size_buckets: Vec<f64>,
+    /// Created from the settings above when the app starts, shared by every clone
+    registry: Arc<OnceLock<Registry>>,
+}
+
+#[derive(Debug)]
+struct Registry {
+    latency_buckets: Vec<f64>,
+    size_buckets: Vec<f64>,
+    series: Mutex<HashMap<SeriesKey, Series>>,
+    in_flight: Arc<AtomicI64>,
+    websockets: Arc<AtomicI64>,
+    sse: Arc<AtomicI64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct SeriesKey {
+    route: Arc<str>,
+    method: Method,
+    status: u16,
+}
+
+#[derive(Debug)]
+struct Series {
+    count: u64,
+    latency: Histogram,
+    request_size: Histogram,
+    response_size: Histogram,
+}
+
+/// Name, help text, buckets and accessor of a histogram family.
+type HistogramFamily<'a> = (&'a str, &'a str, &'a [f64], fn(&Series) -> &Histogram);
+
+#[derive(Debug)]
+struct Histogram {
+    /// Per bucket, not cumulative, the last one is `+Inf`
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(buckets: &[f64]) -> Self {
+        Self {
+            counts: vec![0; buckets.len() + 1],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, buckets: &[f64], value: f64) {
+        let i = buckets.partition_point(|&b| b < value);
+        self.counts[i] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+impl Default for MetricsMiddleware {
+    fn default() -> Self {
+        Self {
+            latency_buckets: LATENCY_BUCKETS.to_vec(),
+            size_buckets: SIZE_BUCKETS.to_vec(),
+            registry: Arc::default(),
+        }
+    }
+}
+
+impl MetricsMiddleware {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the latency histogram buckets, in seconds.
+    ///
+    /// Ignored once the app started or the metrics were rendered.
+    ///
+    /// Default: 5ms to 10s
+    pub fn latency_buckets(mut self, buckets: impl IntoIterator<Item = f64>) -> Self {
+        self.warn_if_started();
+        self.latency_buckets = sorted(buckets);
+        self
+    }
+
+    /// Set the request and response size histogram buckets, in bytes.
+    ///
+    /// Ignored once the app started or the metrics were rendered.
+    ///
+    /// Default: 100B to 100MB
+    pub fn size_buckets(mut self, buckets: impl IntoIterator<Item = f64>) -> Self {
+        self.warn_if_started();
+        self.size_buckets = sorted(buckets);
+        self
+    }
+
+    /// A handler that renders the metrics in the Prometheus text format.
+    pub fn handler(&self) -> MetricsHandler {
+        MetricsHandler {
+            metrics: self.clone(),
+        }
+    }
+
+    /// Renders the metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        self.registry().render()
+    }
+
+    fn registry(&self) -> &Registry {
+        self.registry.get_or_init(|| Registry {
+            latency_buckets: self.latency_buckets.clone(),
+            size_buckets: self.size_buckets.clone(),
+            series: Mutex::default(),
+            in_flight: Arc::default(),
+            websockets: Arc::default(),
+            sse: Arc::default(),
+        })
+    }
+
+    fn warn_if_started(&self) {
+        if self.registry.get().is_some() {
+            tracing::warn!("metrics buckets changed after the metrics were created, ignoring");
+        }
+    }
+}
+
+fn sorted(buckets: impl IntoIterator<Item = f64>) -> Vec<f64> {
+    let mut buckets: Vec<f64> = buckets.into_iter().filter(|b| b.is_finite()).collect();
+    buckets.sort_by(f64::total_cmp);
+    buckets.dedup();
+    buckets
+}
+
+impl Registry {
+    fn record(
+        &self,
+        key: SeriesKey,
+        seconds: f64,
+        request_size: Option<u64>,
+        response_size: Option<u64>,
+    ) {
+        let mut series = self.series.lock().unwrap_or_else(PoisonError::into_inner);
+        let series = series.entry(key).or_insert_with(|| Series {
+            count: 0,
+            latency: Histogram::new(&self.latency_buckets),
+            request_size: Histogram::new(&self.size_buckets),
+            response_size: Histogram::new(&self.size_buckets),
+        });
+
+        series.count += 1;
+        series.latency.observe(&self.latency_buckets, seconds);
+        if let Some(size) = request_size {
+            series.request_size.observe(&self.size_buckets, size as f64);
+        }
+        if let Some(size) = response_size {
+            series
+                .response_size
+                .observe(&self.size_buckets, size as f64);
+        }
+    }
+
+    fn render(&self) -> String {
+        let series = self.series.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut series: Vec<_> = series.iter().collect();
+        series.sort_by(|(a, _), (b, _)| {
+            (&a.route, a.method.as_str(), a.status).cmp(&(&b.route, b.method.as_str(), b.status))
+        });
+
+        let mut out = String::new();
+
+        out.push_str("# HELP http_requests_total Total number of HTTP requests.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for (key, s) in &series {
+            let _ = writeln!(out, "http_requests_total{{{}}} {}", labels(key), s.count);
+        }
+
+        let histograms: [HistogramFamily; 3] = [
+            (
+                "http_request_duration_seconds",
+                "HTTP request latency in seconds.",
+                &self.latency_buckets,
+                |s| &s.latency,
+            ),
+            (
+                "http_request_size_bytes",
+                "HTTP request body size in bytes, when known.",
+                &self.size_buckets,
+                |s| &s.request_size,
+            ),
+            (
+                "http_response_size_bytes",
+                "HTTP response body size in bytes, when known.",
+                &self.size_buckets,
+                |s| &s.response_size,
+            ),
+        ];
+        for (name, help, buckets, histogram) in histograms {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} histogram");
+            for (key, s) in &series {
+                let h = histogram(s);
+                let labels = labels(key);
+                let mut cumulative = 0;
+                for (i, count) in h.counts.iter().enumerate() {
+                    cumulative += count;
+                    let le = buckets
+                        .get(i)
+                        .map_or_else(|| "+Inf".to_string(), f64::to_string);
+                    let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{le}\"}} {cumulative}");
+                }
+                let _ = writeln!(out, "{name}_sum{{{labels}}} {}", h.sum);
+                let _ = writeln!(out, "{name}_count{{{labels}}} {}", h.count);
+            }
+        }
+
+        let gauges = [
+            (
+                "http_requests_in_flight",
+                "HTTP requests currently being handled.",
+                &self.in_flight,
+            ),
+            (
+                "websocket_connections_active",
+                "Open WebSocket connections.",
+                &self.websockets,
+            ),
+            (
+                "sse_connections_active",
+                "Open server-sent event streams.",
+                &self.sse,
+            ),
+        ];
+        for (name, help, gauge) in gauges {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} gauge");
+            let _ = writeln!(out, "{name} {}", gauge.load(Ordering::Relaxed));
+        }
+
+        out
+    }
+}
+
+fn labels(key: &SeriesKey) -> String {
+    format!(
+        "method=\"{}\",route=\"{}\",status=\"{}\"",
+        escape(key.method.as_str()),
+        escape(&key.route),
+        key.status
+    )
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Increments a gauge while alive.
+#[derive(Debug)]
+pub(crate) struct GaugeGuard(Arc<AtomicI64>);
+
+impl GaugeGuard {
+    fn new(gauge: &Arc<AtomicI64>) -> Self {
+        gauge.fetch_add(1, Ordering::Relaxed);
+        Self(gauge.clone())
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Put in the request extensions for websocket upgrades, the connection is counted
+/// once the upgrade succeeds.
+#[cfg(feature = "websocket")]
+#[derive(Clone, Debug)]
+pub(crate) struct WebSocketGauge(Arc<AtomicI64>);
+
+#[cfg(feature = "websocket")]
+impl WebSocketGauge {
+    pub(crate) fn enter(&self) -> GaugeGuard {
+        GaugeGuard::new(&self.0)
+    }
+}
+
+/// Keeps the SSE gauge up until the stream ends or the client goes away.
+struct GaugedBody {
+    inner: HttpBody,
+    _guard: GaugeGuard,
+}
+
+impl HttpBodyTrait for GaugedBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+fn content_length(headers: &http::HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+}
+
+impl Handler<&mut Ctx> for MetricsMiddleware {
+    type Output = ();
+
+    fn on_app_listen_mut(&self, _: &mut App) {
+        // Before the first request, so the handler renders with our buckets
+        self.registry();
+    }
+
+    async fn call(&self, c: &mut Ctx) -> Self::Output {
+        let registry = self.registry();
+        let start = Instant::now();
+        let in_flight = GaugeGuard::new(&registry.in_flight);
+
+        #[cfg(feature = "websocket")]
+        if c.req.is_websocket() {
+            c.req
+                .extensions_mut()
+                .insert(WebSocketGauge(registry.websockets.clone()));
+        }
+
+        let request_size = content_length(c.req.headers());
+
+        c.next().await;
+
+        let seconds = start.elapsed().as_secs_f64();
+        drop(in_flight);
+
+        let response_size = match c.res.inner.body() {
+            HttpBody::Stream(_) => content_length(c.res.headers()),
+            body => body.size_hint().exact(),
+        };
+
+        let is_sse = c
+            .res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/event-stream"));
+        if is_sse && c.res.body().is_stream() {
+            let inner = c.res.take_body();
+            c.res.set_body(HttpBody::from_body(GaugedBody {
+                inner,
+                _guard: GaugeGuard::new(&registry.sse),
+            }));
+        }
+
+        let key = SeriesKey {
+            route: c.req.route.pattern.clone(),
+            method: c.req.method().clone(),
+            status: c.res.inner.status().as_u16(),
+        };
+        registry.record(key, seconds, request_size, response_size);
+    }
+}
+
+/// Serves the metrics of a [`MetricsMiddleware`], see [`MetricsMiddleware::handler`].
+#[derive(Clone, Debug)]
+pub struct MetricsHandler {
+    metrics: MetricsMiddleware,
+}
+
+impl Handler<&mut Ctx> for MetricsHandler {
+    type Output = ();
+
+    async fn call(&self, c: &mut Ctx) -> Self::Output {
+        c.res
+            .header((
+                header::CONTENT_TYPE,
+                "text/plain; version=0.0.4; charset=utf-8",
+            ))
+            .send(self.metrics.render());
+    }
+}
@@ -0,0 +1,374 @@
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, RandomState},
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use super::{Algorithm, Quota, RateLimitStatus, RateLimitStore};
+
+/// Expired keys are swept from a shard once every this many hits.
+const SWEEP_EVERY: u32 = 1024;
+
+/// In-memory store, split into shards so concurrent requests rarely wait on the same lock.
+///
+/// Keys are dropped once their quota is fully available again. Cloning shares the store.
+#[derive(Clone, Debug)]
+pub struct MemoryStore {
+    shards: Arc<[Mutex<Shard>]>,
+    hasher: RandomState,
+}
+
+#[derive(Debug, Default)]
+struct Shard {
+    entries: HashMap<String, Entry>,
+    hits: u32,
+}
+
+#[derive(Debug)]
+struct Entry {
+    state: State,
+    expires: Instant,
+}
+
+#[derive(Debug)]
+enum State {
+    Bucket {
+        tokens: f64,
+        updated: Instant,
+    },
+    Window {
+        start: Instant,
+        current: u32,
+        previous: u32,
+    },
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryStore {
+    /// Creates a store with 32 shards.
+    pub fn new() -> Self {
+        Self::with_shards(32)
+    }
+
+    pub fn with_shards(shards: usize) -> Self {
+        Self {
+            shards: (0..shards.max(1))
+                .map(|_| Mutex::new(Shard::default()))
+                .collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Number of keys currently tracked, including expired ones not swept yet.
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|s| {
+                s.lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .entries
+                    .len()
+            })
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn shard(&self, key: &str) -> &Mutex<Shard> {
+        let hash = self.hasher.hash_one(key) as usize;
+        &self.shards[hash % self.shards.len()]
+    }
+}
+
+impl RateLimitStore for MemoryStore {
+    async fn hit(&self, key: &str, quota: &Quota) -> RateLimitStatus {
+        let now = Instant::now();
+        let mut shard = self
+            .shard(key)
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        shard.hits += 1;
+        if shard.hits >= SWEEP_EVERY {
+            shard.hits = 0;
+            shard.entries.retain(|_, e| e.expires > now);
+        }
+
+        match shard.entries.get_mut(key) {
+            // Starts over if the quota changed algorithm, e.g. two middlewares sharing a store
+            Some(entry) if entry.state.algorithm() == quota.algorithm => entry.hit(quota, now),
+            _ => {
+                let mut entry = Entry {
+                    state: State::new(quota, now),
+                    expires: now,
+                };
+                let status = entry.hit(quota, now);
+                shard.entries.insert(key.to_string(), entry);
+                status
+            }
+        }
+    }
+}
+
+impl Entry {
+    fn hit(&mut self, quota: &Quota, now: Instant) -> RateLimitStatus {
+        let status = self.state.hit(quota, now);
+        self.expires = now + status.reset;
+        status
+    }
+}
+
+impl State {
+    fn new(quota: &Quota, now: Instant) -> Self {
+        match quota.algorithm {
+            Algorithm::TokenBucket => State::Bucket {
+                tokens: quota.limit as f64,
+                updated: now,
+            },
+            Algorithm::SlidingWindow => State::Window {
+                start: now,
+                current: 0,
+                previous: 0,
+            },
+        }
+    }
+
+    fn algorithm(&self) -> Algorithm {
+        match self {
+            State::Bucket { .. } => Algorithm::TokenBucket,
+            State::Window { .. } => Algorithm::SlidingWindow,
+        }
+    }
+
+    fn hit(&mut self, quota: &Quota, now: Instant) -> RateLimitStatus {
+        let limit = quota.limit as f64;
+        let window = quota.window.as_secs_f64().max(f64::EPSILON);
+
+        match self {
+            State::Bucket { tokens, updated } => {
+                // Refills continuously, `limit` tokens per window
+                let rate = limit / window;
+                let elapsed = now.saturating_duration_since(*updated).as_secs_f64();
+                *tokens = (*tokens + elapsed * rate).min(limit);
+                *updated = now;
+
+                let allowed = *tokens >= 1.0;
+                if allowed {
+                    *tokens -= 1.0;
+                }
+
+                let secs = |tokens: f64| {
+                    if rate > 0.0 {
+                        Duration::from_secs_f64(tokens.max(0.0) / rate)
+                    } else {
+                        quota.window
+                    }
+                };
+                RateLimitStatus {
+                    allowed,
+                    remaining: *tokens as u32,
+                    reset: secs(limit - *tokens),
+                    retry_after: (!allowed).then(|| secs(1.0 - *tokens)),
+                }
+            }
+            State::Window {
+                start,
+                current,
+                previous,
+            } => {
+                let periods = (now.saturating_duration_since(*start).as_secs_f64() / window) as u32;
+                if periods > 0 {
+                    *previous = if periods == 1 { *current } else { 0 };
+                    *current = 0;
+                    *start += quota.window * periods;
+                }
+
+                // The previous window counts for the part of it still inside the sliding window
+                let elapsed = now.saturating_duration_since(*start).as_secs_f64();
+                let estimated = *previous as f64 * (1.0 - elapsed / window) + *current as f64;
+
+                let allowed = estimated + 1.0 <= limit;
+                if allowed {
+                    *current += 1;
+                }
+
+                let retry_after = (!allowed).then(|| {
+                    let free = limit - 1.0 - *current as f64;
+                    let secs = if free >= 0.0 && *previous > 0 {
+                        // Wait for the previous window's weight to drop enough
+                        window * (1.0 - free / *previous as f64) - elapsed
+                    } else if *current > 0 && limit >= 1.0 {
+                        // This window is full, wait for it to become the previous one and fade
+                        (window - elapsed) + window * (1.0 - (limit - 1.0) / *current as f64)
+                    } else {
+                        window - elapsed
+                    };
+                    Duration::from_secs_f64(secs.max(0.0))
+                });
+
+                RateLimitStatus {
+                    allowed,
+                    remaining: (limit - estimated - allowed as u8 as f64).max(0.0) as u32,
+                    // Counts stay relevant until the current window has fully slid past
+                    reset: Duration::from_secs_f64((2.0 * window - elapsed).max(0.0)),
+                    retry_after,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quota(algorithm: Algorithm) -> Quota {
+        Quota {
+            algorithm,
+            limit: 10,
+            window: Duration::from_secs(10),
+        }
+    }
+
+    fn secs(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    #[track_caller]
+    fn assert_near(actual: Duration, expected: Duration) {
+        let diff = actual.abs_diff(expected);
+        assert!(
+            diff < Duration::from_millis(1),
+            "{actual:?} != {expected:?}"
+        );
+    }
+
+    #[track_caller]
+    fn assert_status(status: RateLimitStatus, allowed: bool, remaining: u32, reset: f64) {
+        assert_eq!(status.allowed, allowed, "allowed");
+        assert_eq!(status.remaining, remaining, "remaining");
+        assert_near(status.reset, secs(reset));
+        if allowed {
+            assert_eq!(status.retry_after, None);
+        }
+    }
+
+    #[test]
+    fn token_bucket() {
+        let quota = quota(Algorithm::TokenBucket);
+        let t0 = Instant::now();
+        let mut state = State::new(&quota, t0);
+
+        for remaining in (0..10).rev() {
+            // One token refills per second
+            assert_status(
+                state.hit(&quota, t0),
+                true,
+                remaining,
+                10.0 - remaining as f64,
+            );
+        }
+
+        let status = state.hit(&quota, t0);
+        assert_status(status, false, 0, 10.0);
+        assert_near(status.retry_after.unwrap(), secs(1.0));
+
+        let status = state.hit(&quota, t0 + secs(0.5));
+        assert_status(status, false, 0, 9.5);
+        assert_near(status.retry_after.unwrap(), secs(0.5));
+
+        assert_status(state.hit(&quota, t0 + secs(1.5)), true, 0, 9.5);
+    }
+
+    #[test]
+    fn token_bucket_refills_up_to_limit() {
+        let quota = quota(Algorithm::TokenBucket);
+        let t0 = Instant::now();
+        let mut state = State::new(&quota, t0);
+
+        for _ in 0..10 {
+            state.hit(&quota, t0);
+        }
+        assert_status(state.hit(&quota, t0 + secs(3.0)), true, 2, 8.0);
+        assert_status(state.hit(&quota, t0 + secs(100.0)), true, 9, 1.0);
+    }
+
+    #[test]
+    fn sliding_window() {
+        let quota = quota(Algorithm::SlidingWindow);
+        let t0 = Instant::now();
+        let mut state = State::new(&quota, t0);
+
+        for remaining in (0..10).rev() {
+            assert_status(state.hit(&quota, t0), true, remaining, 20.0);
+        }
+
+        // The full window still weighs 10 * 0.9 = 9 one second into the next one
+        let status = state.hit(&quota, t0);
+        assert_status(status, false, 0, 20.0);
+        assert_near(status.retry_after.unwrap(), secs(11.0));
+
+        let status = state.hit(&quota, t0 + secs(10.9));
+        assert_status(status, false, 0, 19.1);
+        assert!(status.retry_after.unwrap() <= secs(0.1));
+
+        assert_status(state.hit(&quota, t0 + secs(11.0)), true, 0, 19.0);
+        // 10 * 0.5 + 1
+        assert_status(state.hit(&quota, t0 + secs(15.0)), true, 3, 15.0);
+    }
+
+    #[test]
+    fn sliding_window_retry_after_previous_fades() {
+        let quota = quota(Algorithm::SlidingWindow);
+        let t0 = Instant::now();
+        let mut state = State::new(&quota, t0);
+
+        for _ in 0..10 {
+            state.hit(&quota, t0);
+        }
+        assert!(state.hit(&quota, t0 + secs(11.0)).allowed);
+
+        // 10 * 0.9 + 1, room again once the previous window weighs 8
+        let status = state.hit(&quota, t0 + secs(11.0));
+        assert_status(status, false, 0, 19.0);
+        assert_near(status.retry_after.unwrap(), secs(1.0));
+
+        assert_status(state.hit(&quota, t0 + secs(12.0)), true, 0, 18.0);
+    }
+
+    #[test]
+    fn sliding_window_forgets_after_two_windows() {
+        let quota = quota(Algorithm::SlidingWindow);
+        let t0 = Instant::now();
+        let mut state = State::new(&quota, t0);
+
+        for _ in 0..11 {
+            state.hit(&quota, t0);
+        }
+        assert_status(state.hit(&quota, t0 + secs(25.0)), true, 9, 15.0);
+    }
+
+    #[tokio::test]
+    async fn store_keys_and_algorithms() {
+        let store = MemoryStore::with_shards(2);
+        let bucket = quota(Algorithm::TokenBucket);
+
+        assert_eq!(store.hit("a", &bucket).await.remaining, 9);
+        assert_eq!(store.hit("a", &bucket).await.remaining, 8);
+        assert_eq!(store.hit("b", &bucket).await.remaining, 9);
+        assert_eq!(store.len(), 2);
+
+        // A different algorithm starts the key over
+        let window = quota(Algorithm::SlidingWindow);
+        assert_eq!(store.hit("a", &window).await.remaining, 9);
+        assert_eq!(store.len(), 2);
+    }
+}
@@ -0,0 +1,137 @@
+use std::{sync::Arc, time::Duration};
+
+use http::header;
+
+mod memory_store;
+mod store;
+
+pub use memory_store::MemoryStore;
+pub use store::{RateLimitStatus, RateLimitStore};
+
+use crate::{ctx::Ctx, handler::Handler, prelude::StatusError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Allows bursts of up to `limit` requests, refilling `limit` tokens per window
+    TokenBucket,
+    /// Allows `limit` requests in any window, weighting the previous window's count
+    SlidingWindow,
+}
+
+/// How many requests are allowed per window, passed to the [`RateLimitStore`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quota {
+    pub algorithm: Algorithm,
+    pub limit: u32,
+    pub window: Duration,
+}
+
+type KeyFn = Arc<dyn Fn(&Ctx) -> String + Send + Sync>;
+
+/// Limits how often a client can hit the routes it's added to.
+///
+/// Responds with `429 Too Many Requests` once the quota is used up. Every response
+/// carries the `RateLimit-Policy`, `RateLimit-Limit`, `RateLimit-Remaining` and
+/// `RateLimit-Reset` headers, plus `Retry-After` when limited.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use maw::{prelude::*, RateLimitMiddleware};
+///
+/// let router = Router::new()
+///     .middleware(RateLimitMiddleware::new(5, Duration::from_secs(60)))
+///     .post("/login", async |_: &mut Ctx| "ok");
+/// ```
+#[derive(Clone)]
+pub struct RateLimitMiddleware<S: RateLimitStore = MemoryStore> {
+    store: S,
+    quota: Quota,
+    key: KeyFn,
+}
+
+impl<S: RateLimitStore + std::fmt::Debug> std::fmt::Debug for RateLimitMiddleware<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RateLimitMiddleware")
+            .field("store", &self.store)
+            .field("quota", &self.quota)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RateLimitMiddleware {
+    /// Allow `limit` requests per `window` for each client IP, using a token bucket
+    /// kept in memory.
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            store: MemoryStore::new(),
+            quota: Quota {
+                algorithm: Algorithm::TokenBucket,
+                limit,
+                window,
+            },
+            key: Arc::new(|c| c.req.ip().to_string()),
+        }
+    }
+}
+
+impl<S: RateLimitStore> RateLimitMiddleware<S> {
+    pub fn store<T: RateLimitStore>(self, store: T) -> RateLimitMiddleware<T> {
+        RateLimitMiddleware {
+            store,
+            quota: self.quota,
+            key: self.key,
+        }
+    }
+
+    /// Default: Algorithm::TokenBucket
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.quota.algorithm = algorithm;
+        self
+    }
+
+    /// Set what requests are counted by, e.g. the logged in user.
+    ///
+    /// Default: `c.req.ip()`
+    pub fn key<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Ctx) -> String + Send + Sync + 'static,
+    {
+        self.key = Arc::new(f);
+        self
+    }
+}
+
+/// Whole seconds, rounded up so clients don't retry too early.
+fn secs(d: Duration) -> String {
+    d.as_secs_f64().ceil().to_string()
+}
+
+impl<S: RateLimitStore> Handler<&mut Ctx> for RateLimitMiddleware<S> {
+    type Output = Result<(), StatusError>;
+
+    async fn call(&self, c: &mut Ctx) -> Self::Output {
+        let key = (self.key)(c);
+        let status = self.store.hit(&key, &self.quota).await;
+
+        c.res
+            .header((
+                "RateLimit-Policy",
+                format!("{};w={}", self.quota.limit, secs(self.quota.window)),
+            ))
+            .header(("RateLimit-Limit", self.quota.limit.to_string()))
+            .header(("RateLimit-Remaining", status.remaining.to_string()))
+            .header((
+                "RateLimit-Reset",
+                secs(status.retry_after.unwrap_or(status.reset)),
+            ));
+
+        if !status.allowed {
+            let retry_after = status.retry_after.unwrap_or(status.reset);
+            c.res.header((header::RETRY_AFTER, secs(retry_after)));
+            return Err(StatusError::too_many_requests());
+        }
+
+        c.next().await;
+        Ok(())
+    }
+}
@@ -0,0 +1,28 @@
+use std::time::Duration;
+
+use super::Quota;
+
+/// Result of counting a request against a [`Quota`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimitStatus {
+    /// Whether the request is allowed through
+    pub allowed: bool,
+    /// Requests left in the current window, after this one
+    pub remaining: u32,
+    /// Time until the quota is fully available again
+    pub reset: Duration,
+    /// Time until the next request would be allowed, set when `allowed` is false
+    pub retry_after: Option<Duration>,
+}
+
+pub trait RateLimitStore: Send + Sync {
+    /// Count one request for `key` and return whether it's allowed.
+    ///
+    /// Shared stores should do this atomically, so concurrent requests can't
+    /// both take the last slot.
+    fn hit(
+        &self,
+        key: &str,
+        quota: &Quota,
+    ) -> impl std::future::Future<Output = RateLimitStatus> + Send;
+}
@@ -0,0 +1,254 @@
+use std::{fmt, sync::Arc};
+
+use http::{HeaderName, HeaderValue};
+use tracing::Instrument as _;
+
+use crate::{ctx::Ctx, handler::Handler, request::Request};
+
+const TRACEPARENT: &str = "traceparent";
+const TRACESTATE: &str = "tracestate";
+
+/// The ID of the current request, stored in the request extensions by [`RequestIdMiddleware`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RequestId(String);
+
+impl RequestId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// W3C trace context of the current request, stored in the request extensions by
+/// [`RequestIdMiddleware`].
+///
+/// Continues the caller's trace if it sent a valid `traceparent`, otherwise starts a new one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceContext {
+    /// 32 lowercase hex characters
+    pub trace_id: String,
+    /// The caller's span, if the trace was continued
+    pub parent_id: Option<String>,
+    /// This request's span, 16 lowercase hex characters
+    pub span_id: String,
+    pub sampled: bool,
+    /// Vendor specific data, passed on as is
+    pub tracestate: Option<String>,
+}
+
+impl TraceContext {
+    fn new() -> Self {
+        Self {
+            trace_id: format!("{:032x}", random_nonzero_u128()),
+            parent_id: None,
+            span_id: format!("{:016x}", random_nonzero_u64()),
+            sampled: true,
+            tracestate: None,
+        }
+    }
+
+    /// Parses a `traceparent` header, returns `None` if it's invalid.
+    fn continue_from(traceparent: &str, tracestate: Option<&str>) -> Option<Self> {
+        let mut parts = traceparent.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let parent_id = parts.next()?;
+        let flags = parts.next()?;
+
+        let is_hex = |s: &str, len: usize| {
+            s.len() == len && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        };
+        // Future versions may append fields, version 00 must have exactly four
+        if !is_hex(version, 2)
+            || version == "ff"
+            || (version == "00" && parts.next().is_some())
+            || !is_hex(trace_id, 32)
+            || !is_hex(parent_id, 16)
+            || !is_hex(flags, 2)
+            || trace_id.bytes().all(|b| b == b'0')
+            || parent_id.bytes().all(|b| b == b'0')
+        {
+            return None;
+        }
+
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        Some(Self {
+            trace_id: trace_id.to_string(),
+            parent_id: Some(parent_id.to_string()),
+            span_id: format!("{:016x}", random_nonzero_u64()),
+            sampled: flags & 1 == 1,
+            tracestate: tracestate
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from),
+        })
+    }
+
+    /// The `traceparent` header to send to downstream services, with this request's span as parent.
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            self.trace_id, self.span_id, self.sampled as u8
+        )
+    }
+
+    /// Headers to send to downstream services to continue the trace.
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = vec![(TRACEPARENT, self.traceparent())];
+        if let Some(state) = &self.tracestate {
+            headers.push((TRACESTATE, state.clone()));
+        }
+        headers
+    }
+}
+
+fn random_nonzero_u128() -> u128 {
+    loop {
+        let n = rand::random::<u128>();
+        if n != 0 {
+            return n;
+        }
+    }
+}
+
+fn random_nonzero_u64() -> u64 {
+    loop {
+        let n = rand::random::<u64>();
+        if n != 0 {
+            return n;
+        }
+    }
+}
+
+/// Gives every request an ID and a W3C trace context.
+///
+/// The ID is taken from the `X-Request-Id` header when present, otherwise generated,
+/// and is echoed back in the response. Everything after this middleware runs inside a
+/// `request` tracing span carrying the ID and trace ID, so add it before `LoggingMiddleware`.
+///
+/// Read them with `c.req.request_id()` and `c.req.trace_context()`.
+#[derive(Clone)]
+pub struct RequestIdMiddleware {
+    header_name: HeaderName,
+    trust_incoming: bool,
+    generator: Arc<dyn Fn() -> String + Send + Sync>,
+}
+
+impl fmt::Debug for RequestIdMiddleware {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestIdMiddleware")
+            .field("header_name", &self.header_name)
+            .field("trust_incoming", &self.trust_incoming)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for RequestIdMiddleware {
+    fn default() -> Self {
+        Self {
+            header_name: HeaderName::from_static("x-request-id"),
+            trust_incoming: true,
+            generator: Arc::new(|| format!("{:032x}", rand::random::<u128>())),
+        }
+    }
+}
+
+impl RequestIdMiddleware {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Default: "X-Request-Id"
+    pub fn header_name(mut self, name: HeaderName) -> Self {
+        self.header_name = name;
+        self
+    }
+
+    /// Whether to reuse the ID sent by the client, turn off if the app isn't behind
+    /// a proxy that sets it.
+    ///
+    /// Default: true
+    pub fn trust_incoming(mut self, trust: bool) -> Self {
+        self.trust_incoming = trust;
+        self
+    }
+
+    /// Set how new IDs are generated.
+    ///
+    /// Default: 32 random hex characters
+    pub fn generator<F>(mut self, f: F) -> Self
+    where
+        F: Fn() -> String + Send + Sync + 'static,
+    {
+        self.generator = Arc::new(f);
+        self
+    }
+
+    fn incoming_id<'a>(&self, c: &'a Ctx) -> Option<&'a str> {
+        if !self.trust_incoming {
+            return None;
+        }
+        // Don't let clients put arbitrary data in our logs
+        c.req.header(&self.header_name).filter(|id| {
+            !id.is_empty()
+                && id.len() <= 128
+                && id
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"-_.:+/=".contains(&b))
+        })
+    }
+}
+
+impl Handler<&mut Ctx> for RequestIdMiddleware {
+    type Output = ();
+
+    async fn call(&self, c: &mut Ctx) -> Self::Output {
+        let id = match self.incoming_id(c) {
+            Some(id) => id.to_string(),
+            None => (self.generator)(),
+        };
+
+        let trace = c
+            .req
+            .header(TRACEPARENT)
+            .and_then(|tp| TraceContext::continue_from(tp, c.req.header(TRACESTATE)))
+            .unwrap_or_else(TraceContext::new);
+
+        let span = tracing::info_span!(
+            "request",
+            route = c.req.route(),
+            request_id = %id,
+            trace_id = %trace.trace_id,
+            span_id = %trace.span_id,
+        );
+
+        c.req.extensions_mut().insert(RequestId(id.clone()));
+        c.req.extensions_mut().insert(trace);
+
+        c.next().instrument(span).await;
+
+        match HeaderValue::try_from(id) {
+            Ok(value) => {
+                c.res.headers_mut().insert(self.header_name.clone(), value);
+            }
+            Err(e) => tracing::error!("invalid request id: {e}"),
+        }
+    }
+}
+
+impl Request {
+    /// The ID set by `RequestIdMiddleware`.
+    pub fn request_id(&self) -> Option<&str> {
+        self.extensions().get::<RequestId>().map(RequestId::as_str)
+    }
+
+    /// The trace context set by `RequestIdMiddleware`.
+    pub fn trace_context(&self) -> Option<&TraceContext> {
+        self.extensions().get::<TraceContext>()
+    }
+}
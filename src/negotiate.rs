@@ -0,0 +1,37 @@
+/// Picks the candidate whose content coding has the highest q-value in an
+/// `Accept-Encoding` header, ties go to the earliest candidate. Codings the client
+/// doesn't accept, explicitly or through `*`, are never picked.
+pub(crate) fn encoding<'a, T>(
+    accept_encoding: &str,
+    candidates: impl IntoIterator<Item = (T, &'a str)>,
+) -> Option<T> {
+    let mut wildcard = None;
+    let mut accepted: Vec<(&str, f32)> = Vec::new();
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or("").trim();
+        let q = parts
+            .find_map(|p| p.trim().strip_prefix("q="))
+            .and_then(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if name == "*" {
+            wildcard = Some(q);
+        } else if !name.is_empty() {
+            accepted.push((name, q));
+        }
+    }
+
+    let mut best: Option<(T, f32)> = None;
+    for (candidate, coding) in candidates {
+        let q = accepted
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(coding))
+            .map(|&(_, q)| q)
+            .or(wildcard)
+            .unwrap_or(0.0);
+        if q > 0.0 && best.as_ref().is_none_or(|(_, best_q)| q > *best_q) {
+            best = Some((candidate, q));
+        }
+    }
+    best.map(|(candidate, _)| candidate)
+}
@@ -0,0 +1,176 @@
+use std::ops::Range;
+
+/// More ranges than this are answered with the whole representation, so a request
+/// can't make us send many small overlapping parts.
+const MAX_RANGES: usize = 16;
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Ranges {
+    /// No usable `Range` header, send the whole representation.
+    Full,
+    /// None of the ranges overlap the representation.
+    Unsatisfiable,
+    Satisfiable(Vec<Range<u64>>),
+}
+
+/// Parses a `bytes=` `Range` header against a representation of `len` bytes.
+///
+/// Malformed headers and other units are ignored, as RFC 9110 allows. Ranges that
+/// start past the end are dropped, the rest are clamped to `len`.
+pub(crate) fn parse(header: &str, len: u64) -> Ranges {
+    let Some((unit, specs)) = header.trim().split_once('=') else {
+        return Ranges::Full;
+    };
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return Ranges::Full;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let Some((start, end)) = spec.split_once('-') else {
+            return Ranges::Full;
+        };
+        let (start, end) = (start.trim(), end.trim());
+
+        let range = if start.is_empty() {
+            // `-500`, the last 500 bytes
+            let Ok(suffix) = end.parse::<u64>() else {
+                return Ranges::Full;
+            };
+            // Nothing to take the suffix of in an empty representation
+            if suffix == 0 || len == 0 {
+                continue;
+            }
+            len.saturating_sub(suffix)..len
+        } else {
+            let Ok(start) = start.parse::<u64>() else {
+                return Ranges::Full;
+            };
+            let end = if end.is_empty() {
+                len
+            } else {
+                match end.parse::<u64>() {
+                    Ok(end) if end >= start => end.saturating_add(1).min(len),
+                    _ => return Ranges::Full,
+                }
+            };
+            if start >= len {
+                continue;
+            }
+            start..end
+        };
+        ranges.push(range);
+    }
+
+    if ranges.len() > MAX_RANGES {
+        Ranges::Full
+    } else if ranges.is_empty() {
+        Ranges::Unsatisfiable
+    } else {
+        Ranges::Satisfiable(ranges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn satisfiable(ranges: &[Range<u64>]) -> Ranges {
+        Ranges::Satisfiable(ranges.to_vec())
+    }
+
+    #[test]
+    fn closed() {
+        assert_eq!(parse("bytes=0-499", 1000), satisfiable(&[0..500]));
+        assert_eq!(parse("bytes=500-999", 1000), satisfiable(&[500..1000]));
+        assert_eq!(parse("bytes=5-5", 1000), satisfiable(&[5..6]));
+    }
+
+    #[test]
+    fn end_clamped_to_len() {
+        assert_eq!(parse("bytes=500-5000", 1000), satisfiable(&[500..1000]));
+        assert_eq!(
+            parse("bytes=0-18446744073709551615", 10),
+            satisfiable(&[0..10])
+        );
+    }
+
+    #[test]
+    fn suffix() {
+        assert_eq!(parse("bytes=-500", 1000), satisfiable(&[500..1000]));
+        assert_eq!(parse("bytes=-5000", 1000), satisfiable(&[0..1000]));
+        assert_eq!(parse("bytes=-0", 1000), Ranges::Unsatisfiable);
+    }
+
+    #[test]
+    fn open_ended() {
+        assert_eq!(parse("bytes=500-", 1000), satisfiable(&[500..1000]));
+        assert_eq!(parse("bytes=999-", 1000), satisfiable(&[999..1000]));
+    }
+
+    #[test]
+    fn multiple() {
+        assert_eq!(
+            parse("bytes=0-9, 20-29 ,-5", 100),
+            satisfiable(&[0..10, 20..30, 95..100])
+        );
+        // Unsatisfiable ranges are dropped from the set
+        assert_eq!(parse("bytes=0-9,2000-", 100), satisfiable(&[0..10]));
+    }
+
+    #[test]
+    fn end_before_start() {
+        assert_eq!(parse("bytes=500-499", 1000), Ranges::Full);
+    }
+
+    #[test]
+    fn start_at_or_past_end() {
+        assert_eq!(parse("bytes=1000-", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse("bytes=1000-1999", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse("bytes=5000-", 1000), Ranges::Unsatisfiable);
+    }
+
+    #[test]
+    fn zero_length() {
+        assert_eq!(parse("bytes=0-", 0), Ranges::Unsatisfiable);
+        assert_eq!(parse("bytes=0-499", 0), Ranges::Unsatisfiable);
+        assert_eq!(parse("bytes=-500", 0), Ranges::Unsatisfiable);
+    }
+
+    #[test]
+    fn range_cap() {
+        let specs = |n: u64| {
+            (0..n)
+                .map(|i| format!("{i}-{i}"))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+
+        let Ranges::Satisfiable(ranges) = parse(&format!("bytes={}", specs(16)), 100) else {
+            panic!("16 ranges should be served");
+        };
+        assert_eq!(ranges.len(), 16);
+        assert_eq!(parse(&format!("bytes={}", specs(17)), 100), Ranges::Full);
+    }
+
+    #[test]
+    fn malformed() {
+        for header in [
+            "bytes=abc",
+            "bytes=1-x",
+            "bytes=x-1",
+            "bytes=--5",
+            "bytes=0-9,oops",
+            "bytes 0-9",
+        ] {
+            assert_eq!(parse(header, 1000), Ranges::Full, "{header}");
+        }
+    }
+
+    #[test]
+    fn other_units() {
+        assert_eq!(parse("items=0-9", 1000), Ranges::Full);
+        // Units are case-insensitive
+        assert_eq!(parse("Bytes=0-9", 1000), satisfiable(&[0..10]));
+    }
+}
@@ -1,9 +1,13 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use bytes::Bytes;
 use http::{HeaderMap, HeaderValue, Method, Uri, Version, header::AsHeaderName};
 use http_body_util::BodyExt;
-use hyper::body::Incoming as IncomingBody;
 use mime_guess::{Mime, mime};
 use multer::Multipart;
 use serde::de::DeserializeOwned;
@@ -12,17 +16,21 @@ use smol_str::SmolStr;
 use crate::{
     any_map::{AnyMap, CloneableAny},
     app::App,
+    ip,
     prelude::StatusError,
+    response::HttpBody,
+    router::RouteInfo,
 };
 
 pub struct Request {
     pub(crate) app: Arc<App>,
     pub(crate) parts: http::request::Parts,
-    pub(crate) body: Option<IncomingBody>,
+    pub(crate) body: Option<HttpBody>,
     pub params: HashMap<SmolStr, SmolStr>,
+    pub(crate) route: Arc<RouteInfo>,
     pub locals: AnyMap<dyn CloneableAny>,
     pub(crate) cached_body: Option<Bytes>,
-    pub(crate) ip: std::net::SocketAddr,
+    pub(crate) ip: SocketAddr,
     /// Max body size that the server accepts.
     ///
     /// Default: 4MB
@@ -33,9 +41,10 @@ impl Request {
     #[inline]
     pub(crate) fn new(
         app: Arc<App>,
-        request: http::Request<IncomingBody>,
+        request: http::Request<HttpBody>,
         params: HashMap<SmolStr, SmolStr>,
-        peer_addr: std::net::SocketAddr,
+        route: Arc<RouteInfo>,
+        peer_addr: SocketAddr,
     ) -> Self {
         let (parts, body) = request.into_parts();
         let body_limit = app.body_limit;
@@ -44,6 +53,7 @@ impl Request {
             parts,
             body: Some(body),
             params,
+            route,
             locals: AnyMap::new(),
             cached_body: None,
             ip: peer_addr,
@@ -51,6 +61,22 @@ impl Request {
         }
     }
 
+    /// A copy of the request without its body, which can only be read once.
+    #[cfg(feature = "middleware-cache")]
+    pub(crate) fn fork(&self) -> Self {
+        Request {
+            app: self.app.clone(),
+            parts: self.parts.clone(),
+            body: None,
+            params: self.params.clone(),
+            route: self.route.clone(),
+            locals: self.locals.clone(),
+            cached_body: self.cached_body.clone(),
+            ip: self.ip,
+            body_limit: self.body_limit,
+        }
+    }
+
     #[inline]
     pub fn app(&self) -> &App {
         &self.app
@@ -101,6 +127,13 @@ impl Request {
         &self.parts.method
     }
 
+    /// Whether this is a `HEAD` request. Its body is never sent, so handlers can skip
+    /// producing one, as long as they still set the `Content-Length` a `GET` would get.
+    #[inline]
+    pub fn is_head(&self) -> bool {
+        self.parts.method == Method::HEAD
+    }
+
     #[inline]
     pub fn uri(&self) -> &Uri {
         &self.parts.uri
@@ -116,6 +149,16 @@ impl Request {
         &mut self.parts.headers
     }
 
+    #[inline]
+    pub fn extensions(&self) -> &http::Extensions {
+        &self.parts.extensions
+    }
+
+    #[inline]
+    pub fn extensions_mut(&mut self) -> &mut http::Extensions {
+        &mut self.parts.extensions
+    }
+
     /// Returns the specified Header value as a &str.
     #[inline]
     pub fn header<K>(&self, key: K) -> Option<&str>
@@ -137,6 +180,30 @@ impl Request {
         self.parts.version
     }
 
+    /// The route pattern that matched this request, e.g. `/user/{id}`.
+    #[inline]
+    pub fn route(&self) -> &str {
+        self.route.pattern()
+    }
+
+    /// The name given to the matched route with `Router::name`.
+    #[inline]
+    pub fn route_name(&self) -> Option<&str> {
+        self.route.name()
+    }
+
+    /// Metadata of type `T` attached to the matched route with `Router::meta`.
+    #[inline]
+    pub fn route_meta<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.route.meta::<T>()
+    }
+
+    /// Everything known about the matched route.
+    #[inline]
+    pub fn route_info(&self) -> &RouteInfo {
+        &self.route
+    }
+
     /// Set the maximum body size that the request will read.
     ///
     /// Default: 4MB
@@ -145,12 +212,51 @@ impl Request {
     }
 
     /// Take the raw body, leaving `None` in its place.
+    ///
+    /// Unlike [`take_body`](Self::take_body), the body is never decompressed.
     #[inline]
-    pub fn take_body(&mut self) -> Option<IncomingBody> {
+    pub fn take_raw_body(&mut self) -> Option<HttpBody> {
         self.body.take()
     }
 
-    /// Get raw body bytes.
+    /// Take the body, leaving `None` in its place.
+    ///
+    /// With the `decompression` feature, a body sent with `Content-Encoding: gzip`, `deflate`
+    /// or `br` is decompressed, and the decompressed size is limited by the body limit.
+    /// The `Content-Encoding` and `Content-Length` headers are removed in that case.
+    /// Any other encoding makes the body fail on the first read.
+    pub fn take_body(&mut self) -> Option<HttpBody> {
+        let body = self.body.take()?;
+
+        #[cfg(feature = "decompression")]
+        match crate::decompression::decoder_for(self.headers(), self.body_limit) {
+            Ok(Some(decoder)) => {
+                self.parts.headers.remove(http::header::CONTENT_ENCODING);
+                self.parts.headers.remove(http::header::CONTENT_LENGTH);
+                return Some(HttpBody::from_body(crate::decompression::DecodedBody::new(
+                    body, decoder,
+                )));
+            }
+            Ok(None) => {}
+            Err(e) => {
+                return Some(HttpBody::stream(futures_util::stream::once(
+                    std::future::ready(Err(e.into())),
+                )));
+            }
+        }
+
+        Some(body)
+    }
+
+    /// Returns an error if the body is encoded with an unsupported `Content-Encoding`.
+    #[inline]
+    fn check_content_encoding(&self) -> Result<(), BodyError> {
+        #[cfg(feature = "decompression")]
+        crate::decompression::decoder_for(self.headers(), 0)?;
+        Ok(())
+    }
+
+    /// Get body bytes, decompressed if the `decompression` feature is enabled.
     ///
     /// If body has already been read, returns the cached bytes. (Limits are not re-applied.)
     ///
@@ -160,12 +266,12 @@ impl Request {
         if let Some(ref bytes) = self.cached_body {
             return Ok(bytes);
         }
+        self.check_content_encoding()?;
         let limit = self.body_limit;
-        let raw = self
-            .body
-            .as_mut()
+        let body = self
+            .take_body()
             .ok_or_else(|| BodyError::Collect("body already taken".into()))?;
-        let limited = http_body_util::Limited::new(raw, limit);
+        let limited = http_body_util::Limited::new(body, limit);
         let collected = limited.collect().await.map_err(BodyError::Collect)?;
         let bytes = collected.to_bytes();
         self.cached_body = Some(bytes);
@@ -255,25 +361,72 @@ impl Request {
 
     #[inline]
     pub fn size_hint(&self) -> hyper::body::SizeHint {
-        match &self.body {
-            Some(body) => hyper::body::Body::size_hint(body),
-            None => hyper::body::SizeHint::default(),
+        let Some(body) = &self.body else {
+            return hyper::body::SizeHint::default();
+        };
+        // Bodies from a connection are streams, whose length only the header knows
+        match self
+            .header(http::header::CONTENT_LENGTH)
+            .and_then(|v| v.parse().ok())
+        {
+            Some(len) => hyper::body::SizeHint::with_exact(len),
+            None => hyper::body::Body::size_hint(body),
         }
     }
 
-    #[inline]
-    fn extract_ip_from_header(&self, header_name: &str) -> String {
-        self.header(header_name).unwrap_or_default().to_string()
+    /// The addresses in the proxy header, from the client to the last proxy.
+    fn forwarded_chain(&self, header_name: &str) -> Vec<Option<IpAddr>> {
+        let values = self
+            .headers()
+            .get_all(header_name)
+            .into_iter()
+            .filter_map(|v| v.to_str().ok());
+        if header_name.eq_ignore_ascii_case(http::header::FORWARDED.as_str()) {
+            ip::forwarded(values)
+        } else {
+            ip::x_forwarded_for(values)
+        }
     }
 
-    #[inline]
-    pub fn ip(&self) -> String {
-        if let Some(ref f) = self.app.proxy_header_fn {
-            if let Some(header_name) = f() {
-                return self.extract_ip_from_header(&header_name);
+    /// The client's IP address.
+    ///
+    /// Without a proxy header or trusted proxies this is the peer address of the connection.
+    /// With trusted proxies, the proxy header is only read when the peer is one of them,
+    /// and the client is the right-most address in it that isn't a trusted proxy.
+    /// Without trusted proxies, the proxy header is believed from any peer, and the
+    /// right-most address in it is used.
+    ///
+    /// See [`App::proxy_header`] and [`App::trusted_proxies`].
+    pub fn ip(&self) -> IpAddr {
+        let peer = self.ip.ip().to_canonical();
+        let trusted = &self.app.trusted_proxies;
+
+        let header_name = match &self.app.proxy_header_fn {
+            Some(f) => f(),
+            None if !trusted.is_empty() => {
+                Some(if self.headers().contains_key(http::header::FORWARDED) {
+                    http::header::FORWARDED.to_string()
+                } else {
+                    "X-Forwarded-For".to_string()
+                })
             }
+            None => None,
+        };
+        let Some(header_name) = header_name else {
+            return peer;
+        };
+
+        let chain = self.forwarded_chain(&header_name);
+        if trusted.is_empty() {
+            return chain.last().copied().flatten().unwrap_or(peer);
         }
-        self.ip.to_string()
+        ip::client_ip(peer, &chain, trusted)
+    }
+
+    /// The address of the connection's peer, which is the last proxy when behind one.
+    #[inline]
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.ip
     }
 
     #[inline]
@@ -281,6 +434,84 @@ impl Request {
         self.ip.ip().is_loopback()
     }
 
+    fn header_date(&self, name: http::header::HeaderName) -> Option<SystemTime> {
+        self.header(name)
+            .and_then(|v| httpdate::parse_http_date(v).ok())
+    }
+
+    /// Whether the client's cached copy is still valid, so a `304 Not Modified` can be sent
+    /// instead of the body. Checks `If-None-Match` against `etag`, or when absent,
+    /// `If-Modified-Since` against `last_modified`.
+    pub fn is_fresh(&self, etag: Option<&str>, last_modified: Option<SystemTime>) -> bool {
+        if self
+            .header(http::header::CACHE_CONTROL)
+            .is_some_and(|cc| cc.contains("no-cache"))
+        {
+            return false;
+        }
+
+        if let Some(if_none_match) = self.header(http::header::IF_NONE_MATCH) {
+            return etag.is_some_and(|etag| etag_matches(if_none_match, etag, false));
+        }
+
+        match (
+            self.header_date(http::header::IF_MODIFIED_SINCE),
+            last_modified,
+        ) {
+            (Some(since), Some(modified)) => truncate_to_secs(modified) <= since,
+            _ => false,
+        }
+    }
+
+    /// Evaluates `If-Match`, `If-Unmodified-Since` and `If-None-Match` against the current
+    /// validators of the resource, before a request changes it.
+    ///
+    /// Returns `412 Precondition Failed` when the client's copy is out of date. Pass `None`
+    /// for both when the resource doesn't exist.
+    pub fn check_preconditions(
+        &self,
+        etag: Option<&str>,
+        last_modified: Option<SystemTime>,
+    ) -> Result<(), StatusError> {
+        let exists = etag.is_some() || last_modified.is_some();
+        let failed = if let Some(if_match) = self.header(http::header::IF_MATCH) {
+            if if_match.trim() == "*" {
+                !exists
+            } else {
+                !etag.is_some_and(|etag| etag_matches(if_match, etag, true))
+            }
+        } else if let Some(since) = self.header_date(http::header::IF_UNMODIFIED_SINCE) {
+            last_modified.is_some_and(|modified| truncate_to_secs(modified) > since)
+        } else {
+            false
+        };
+
+        let failed = failed
+            || self
+                .header(http::header::IF_NONE_MATCH)
+                .is_some_and(|inm| match inm.trim() {
+                    "*" => exists,
+                    inm => etag.is_some_and(|etag| etag_matches(inm, etag, false)),
+                });
+
+        if failed {
+            return Err(StatusError::precondition_failed());
+        }
+        Ok(())
+    }
+
+    /// Whether a `Range` request may be answered with part of the representation, which
+    /// `If-Range` only allows if it still strongly matches the client's copy.
+    pub(crate) fn if_range(&self, etag: Option<&str>, last_modified: Option<SystemTime>) -> bool {
+        let Some(if_range) = self.header(http::header::IF_RANGE) else {
+            return true;
+        };
+        if let Ok(date) = httpdate::parse_http_date(if_range) {
+            return last_modified.is_some_and(|modified| truncate_to_secs(modified) == date);
+        }
+        etag.is_some_and(|etag| if_range.trim() != "*" && etag_matches(if_range, etag, true))
+    }
+
     #[inline]
     pub fn query<T: DeserializeOwned>(&self) -> Result<T, QueryError> {
         let qs = self.parts.uri.query().unwrap_or("");
@@ -324,6 +555,8 @@ impl Request {
                     .ok_or(MultipartError::MissingBoundary)
             })?;
 
+        self.check_content_encoding()
+            .map_err(|_| MultipartError::UnsupportedEncoding)?;
         let body = self.take_body().ok_or(MultipartError::BodyTaken)?;
         let stream = body.into_data_stream();
 
@@ -370,11 +603,17 @@ pub enum BodyError {
 
     #[error("Unsupported media type")]
     UnsupportedMediaType,
+
+    #[error("Unsupported content encoding")]
+    UnsupportedEncoding,
 }
 
 impl From<BodyError> for StatusError {
     fn from(e: BodyError) -> Self {
         match e {
+            BodyError::Collect(e) if exceeds_limit(&*e) => {
+                StatusError::payload_too_large().brief("Body is too large")
+            }
             BodyError::Collect(_) => StatusError::bad_request().brief("Failed to read body"),
             BodyError::InvalidUtf8(_) => {
                 StatusError::bad_request().brief("Body is not valid UTF-8")
@@ -385,10 +624,22 @@ impl From<BodyError> for StatusError {
             BodyError::UnsupportedMediaType => {
                 StatusError::unsupported_media_type().brief("Unsupported media type")
             }
+            BodyError::UnsupportedEncoding => {
+                StatusError::unsupported_media_type().brief("Unsupported content encoding")
+            }
         }
     }
 }
 
+/// Whether the body went over the body limit, as sent or once decompressed.
+fn exceeds_limit(e: &(dyn std::error::Error + 'static)) -> bool {
+    #[cfg(feature = "decompression")]
+    if e.is::<crate::decompression::DecompressedTooLarge>() {
+        return true;
+    }
+    e.is::<http_body_util::LengthLimitError>()
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ParseError {
     #[error("Failed to parse JSON")]
@@ -469,6 +720,33 @@ impl From<QueryError> for StatusError {
     }
 }
 
+/// Whether `etag` is in the comma separated list of a conditional header.
+///
+/// Strong comparison needs both tags to be strong, weak comparison ignores the `W/` prefix.
+fn etag_matches(list: &str, etag: &str, strong: bool) -> bool {
+    let etag = etag.trim();
+    if strong && etag.starts_with("W/") {
+        return false;
+    }
+    let opaque = etag.trim_start_matches("W/");
+    list.split(',').map(str::trim).any(|candidate| {
+        candidate == "*"
+            || if strong {
+                candidate == etag
+            } else {
+                candidate.trim_start_matches("W/") == opaque
+            }
+    })
+}
+
+/// HTTP dates only have second precision.
+fn truncate_to_secs(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => UNIX_EPOCH + Duration::from_secs(d.as_secs()),
+        Err(_) => time,
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum MultipartError {
     #[error("Missing Content-Type header")]
@@ -479,10 +757,198 @@ pub enum MultipartError {
     MissingBoundary,
     #[error("Body already taken")]
     BodyTaken,
+    #[error("Unsupported content encoding")]
+    UnsupportedEncoding,
 }
 
 impl From<MultipartError> for StatusError {
     fn from(e: MultipartError) -> Self {
-        StatusError::bad_request().brief(e.to_string())
+        match e {
+            MultipartError::UnsupportedEncoding => {
+                StatusError::unsupported_media_type().brief(e.to_string())
+            }
+            _ => StatusError::bad_request().brief(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        let mut req = http::Request::builder().uri("/");
+        for &(name, value) in headers {
+            req = req.header(name, value);
+        }
+        Request::new(
+            Arc::new(App::new()),
+            req.body(HttpBody::Empty).unwrap(),
+            HashMap::new(),
+            Arc::new(RouteInfo::new("/".into())),
+            "127.0.0.1:1234".parse().unwrap(),
+        )
+    }
+
+    /// A sub-second modification time, HTTP dates only carry the seconds.
+    fn modified() -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(1_700_000_000_500)
+    }
+
+    fn date(secs_from_modified: i64) -> String {
+        let secs = 1_700_000_000u64
+            .checked_add_signed(secs_from_modified)
+            .unwrap();
+        httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn etag_weak_and_strong() {
+        assert!(etag_matches(r#""a""#, r#""a""#, true));
+        assert!(!etag_matches(r#"W/"a""#, r#""a""#, true));
+        assert!(!etag_matches(r#""a""#, r#"W/"a""#, true));
+        assert!(!etag_matches(r#"W/"a""#, r#"W/"a""#, true));
+
+        assert!(etag_matches(r#"W/"a""#, r#""a""#, false));
+        assert!(etag_matches(r#""a""#, r#"W/"a""#, false));
+        assert!(etag_matches(r#"W/"a""#, r#"W/"a""#, false));
+        assert!(!etag_matches(r#""b""#, r#""a""#, false));
+    }
+
+    #[test]
+    fn etag_lists_and_star() {
+        assert!(etag_matches(r#""x", "a" ,"y""#, r#""a""#, true));
+        assert!(etag_matches(r#""x",W/"a""#, r#""a""#, false));
+        assert!(!etag_matches(r#""x", "y""#, r#""a""#, false));
+        assert!(etag_matches("*", r#""a""#, true));
+        assert!(!etag_matches("*", r#"W/"a""#, true));
+    }
+
+    #[test]
+    fn fresh_if_none_match() {
+        let etag = Some(r#""a""#);
+        assert!(request(&[("if-none-match", r#""x", W/"a""#)]).is_fresh(etag, None));
+        assert!(request(&[("if-none-match", "*")]).is_fresh(etag, None));
+        assert!(!request(&[("if-none-match", r#""b""#)]).is_fresh(etag, None));
+        assert!(!request(&[("if-none-match", "*")]).is_fresh(None, None));
+        assert!(!request(&[]).is_fresh(etag, Some(modified())));
+    }
+
+    #[test]
+    fn fresh_if_modified_since() {
+        let modified = Some(modified());
+        assert!(request(&[("if-modified-since", &date(0))]).is_fresh(None, modified));
+        assert!(request(&[("if-modified-since", &date(60))]).is_fresh(None, modified));
+        assert!(!request(&[("if-modified-since", &date(-1))]).is_fresh(None, modified));
+        assert!(!request(&[("if-modified-since", "yesterday")]).is_fresh(None, modified));
+        assert!(!request(&[("if-modified-since", &date(0))]).is_fresh(None, None));
+    }
+
+    #[test]
+    fn if_none_match_takes_precedence() {
+        let modified = Some(modified());
+        let req = request(&[
+            ("if-none-match", r#""b""#),
+            ("if-modified-since", &date(60)),
+        ]);
+        assert!(!req.is_fresh(Some(r#""a""#), modified));
+
+        let req = request(&[
+            ("if-none-match", r#""a""#),
+            ("if-modified-since", &date(-60)),
+        ]);
+        assert!(req.is_fresh(Some(r#""a""#), modified));
+    }
+
+    #[test]
+    fn no_cache_is_never_fresh() {
+        let req = request(&[("if-none-match", r#""a""#), ("cache-control", "no-cache")]);
+        assert!(!req.is_fresh(Some(r#""a""#), None));
+    }
+
+    #[test]
+    fn preconditions_if_match() {
+        let etag = Some(r#""a""#);
+        assert!(
+            request(&[("if-match", r#""x", "a""#)])
+                .check_preconditions(etag, None)
+                .is_ok()
+        );
+        assert!(
+            request(&[("if-match", "*")])
+                .check_preconditions(etag, None)
+                .is_ok()
+        );
+
+        let failed = request(&[("if-match", r#""b""#)]).check_preconditions(etag, None);
+        assert_eq!(
+            failed.unwrap_err().code,
+            http::StatusCode::PRECONDITION_FAILED
+        );
+        // Strong comparison
+        let req = request(&[("if-match", r#"W/"a""#)]);
+        assert!(req.check_preconditions(etag, None).is_err());
+        let req = request(&[("if-match", r#""a""#)]);
+        assert!(req.check_preconditions(Some(r#"W/"a""#), None).is_err());
+        // Nothing to match when the resource doesn't exist
+        assert!(
+            request(&[("if-match", "*")])
+                .check_preconditions(None, None)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn preconditions_if_unmodified_since() {
+        let modified = Some(modified());
+        let ok = |since: &str| {
+            request(&[("if-unmodified-since", since)])
+                .check_preconditions(None, modified)
+                .is_ok()
+        };
+        assert!(ok(&date(0)));
+        assert!(ok(&date(60)));
+        assert!(!ok(&date(-1)));
+        assert!(ok("not a date"));
+
+        // `If-Match` wins over `If-Unmodified-Since`
+        let req = request(&[("if-match", r#""a""#), ("if-unmodified-since", &date(-60))]);
+        assert!(req.check_preconditions(Some(r#""a""#), modified).is_ok());
+    }
+
+    #[test]
+    fn preconditions_if_none_match() {
+        let etag = Some(r#""a""#);
+        // Creating a resource only if it doesn't exist yet
+        assert!(
+            request(&[("if-none-match", "*")])
+                .check_preconditions(None, None)
+                .is_ok()
+        );
+        assert!(
+            request(&[("if-none-match", "*")])
+                .check_preconditions(etag, None)
+                .is_err()
+        );
+        assert!(
+            request(&[("if-none-match", r#"W/"a""#)])
+                .check_preconditions(etag, None)
+                .is_err()
+        );
+        assert!(
+            request(&[("if-none-match", r#""b""#)])
+                .check_preconditions(etag, None)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn preconditions_without_headers() {
+        assert!(
+            request(&[])
+                .check_preconditions(Some(r#""a""#), Some(modified()))
+                .is_ok()
+        );
+        assert!(request(&[]).check_preconditions(None, None).is_ok());
     }
 }
@@ -1,24 +1,32 @@
 use std::{
     error::Error as StdError,
     fmt,
+    hash::{BuildHasher, Hasher, RandomState},
     pin::Pin,
-    sync::Arc,
+    sync::{Arc, Mutex, PoisonError},
     task::{Context, Poll},
+    time::UNIX_EPOCH,
 };
 
-use bytes::Bytes;
-use futures_util::{Stream, StreamExt};
+use bytes::{Bytes, BytesMut};
+use futures_util::{Stream, StreamExt, TryStreamExt, future, stream};
 use http::{
-    self, HeaderMap, HeaderName, HeaderValue, StatusCode,
+    self, HeaderMap, HeaderName, HeaderValue, Method, StatusCode,
     header::{self, InvalidHeaderName},
 };
 use http_body::{Body as HttpBodyTrait, Frame, SizeHint};
-use http_body_util::Full;
+use http_body_util::{BodyExt, Full};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::{io::ReaderStream, sync::CancellationToken};
 
 use crate::{
     any_map::{AnyMap, SerializableAny},
     app::App,
     error::Error,
+    prelude::StatusError,
+    range::{self, Ranges},
+    request::Request,
+    sse::{SseOptions, SseStream},
 };
 
 pub type BoxError = Box<dyn StdError + Send + Sync>;
@@ -68,6 +76,103 @@ impl HttpBody {
     {
         HttpBody::Stream(StreamKind::Frames(Box::pin(stream)))
     }
+
+    /// Wraps any `http_body::Body`, e.g. a hyper `Incoming` or a body produced by a tower service.
+    pub fn from_body<B>(body: B) -> Self
+    where
+        B: HttpBodyTrait<Data = Bytes> + Send + 'static,
+        B::Error: Into<BoxError>,
+    {
+        HttpBody::Stream(StreamKind::Frames(Box::pin(SyncBody(Mutex::new(
+            Box::pin(body),
+        )))))
+    }
+
+    #[inline]
+    pub fn is_stream(&self) -> bool {
+        matches!(self, HttpBody::Stream(_))
+    }
+
+    /// Maps every data chunk of the body through `f`.
+    ///
+    /// Trailer frames are passed through untouched. A `Full` body is mapped once
+    /// as a single chunk, an `Empty` body is returned as is.
+    pub fn map_stream<F>(self, mut f: F) -> Self
+    where
+        F: FnMut(Bytes) -> Bytes + Send + Sync + 'static,
+    {
+        match self {
+            HttpBody::Empty => HttpBody::Empty,
+            HttpBody::Full(full) => HttpBody::full(f(full_into_bytes(full))),
+            HttpBody::Stream(StreamKind::Bytes(stream)) => {
+                HttpBody::stream(stream.map(move |res| res.map(&mut f)))
+            }
+            HttpBody::Stream(StreamKind::Frames(stream)) => HttpBody::stream_frames(
+                stream.map(move |res| res.map(|frame| frame.map_data(&mut f))),
+            ),
+        }
+    }
+
+    /// Collects the whole body into memory, failing once more than `limit` bytes were read.
+    ///
+    /// Trailers are dropped.
+    pub async fn collect(self, limit: usize) -> Result<Bytes, ResponseBodyError> {
+        match self {
+            HttpBody::Empty => Ok(Bytes::new()),
+            HttpBody::Full(full) => {
+                let bytes = full_into_bytes(full);
+                if bytes.len() > limit {
+                    return Err(ResponseBodyError::TooLarge);
+                }
+                Ok(bytes)
+            }
+            body @ HttpBody::Stream(_) => {
+                let collected = http_body_util::Limited::new(body, limit)
+                    .collect()
+                    .await
+                    .map_err(|e| {
+                        if e.is::<http_body_util::LengthLimitError>() {
+                            ResponseBodyError::TooLarge
+                        } else {
+                            ResponseBodyError::Collect(e)
+                        }
+                    })?;
+                Ok(collected.to_bytes())
+            }
+        }
+    }
+}
+
+// Streams inside `HttpBody` have to be `Sync`, most foreign bodies are only `Send`.
+// The mutex is never actually locked, `get_mut` is enough since polling takes `&mut self`.
+struct SyncBody<B>(Mutex<Pin<Box<B>>>);
+
+impl<B> Stream for SyncBody<B>
+where
+    B: HttpBodyTrait<Data = Bytes>,
+    B::Error: Into<BoxError>,
+{
+    type Item = Result<Frame<Bytes>, BoxError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let body = self
+            .get_mut()
+            .0
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        body.as_mut()
+            .poll_frame(cx)
+            .map(|opt| opt.map(|res| res.map_err(Into::into)))
+    }
+}
+
+/// `Full` always yields its data on the first poll, so there is no need to await it.
+fn full_into_bytes(mut full: Full<Bytes>) -> Bytes {
+    let mut cx = Context::from_waker(std::task::Waker::noop());
+    match Pin::new(&mut full).poll_frame(&mut cx) {
+        Poll::Ready(Some(Ok(frame))) => frame.into_data().unwrap_or_default(),
+        _ => Bytes::new(),
+    }
 }
 
 impl HttpBodyTrait for HttpBody {
@@ -189,6 +294,25 @@ impl Response {
         self
     }
 
+    /// Adds `field` to the `Vary` header, unless it's already listed.
+    pub fn vary(&mut self, field: &str) -> &mut Self {
+        let listed = self
+            .inner
+            .headers()
+            .get_all(header::VARY)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|v| {
+                let v = v.trim();
+                v == "*" || v.eq_ignore_ascii_case(field)
+            });
+        if !listed {
+            self.append(header::VARY.as_str(), field);
+        }
+        self
+    }
+
     /// Appends a value to the HTTP response header field.
     /// If the header is not already set, it creates the header with the specified value.
     ///
@@ -218,6 +342,83 @@ impl Response {
         self
     }
 
+    #[inline]
+    pub fn body(&self) -> &HttpBody {
+        self.inner.body()
+    }
+
+    /// Takes the response body, leaving an empty body in its place.
+    ///
+    /// Useful in middlewares after `c.next().await` to transform what the handler produced.
+    #[inline]
+    pub fn take_body(&mut self) -> HttpBody {
+        std::mem::take(self.inner.body_mut())
+    }
+
+    /// Replaces the response body.
+    ///
+    /// Removes any `Content-Length` header, as it may no longer match the new body.
+    #[inline]
+    pub fn set_body(&mut self, body: HttpBody) {
+        self.inner.headers_mut().remove(header::CONTENT_LENGTH);
+        *self.inner.body_mut() = body;
+    }
+
+    /// Buffers the response body into memory and returns it.
+    ///
+    /// Streaming bodies are read until the end, failing once more than `limit` bytes were read.
+    /// On success the body is kept as a buffered body so it is still sent to the client.
+    /// On failure the bytes read so far are put back in front of the rest of the stream,
+    /// so the client gets the same body it would have without this call.
+    pub async fn read_body(&mut self, limit: usize) -> Result<Bytes, ResponseBodyError> {
+        let body = match self.take_body() {
+            HttpBody::Stream(kind) => HttpBody::Stream(kind),
+            body => {
+                let bytes = body.collect(usize::MAX).await?;
+                *self.inner.body_mut() = HttpBody::full(bytes.clone());
+                if bytes.len() > limit {
+                    return Err(ResponseBodyError::TooLarge);
+                }
+                return Ok(bytes);
+            }
+        };
+
+        let mut rest = http_body_util::BodyStream::new(body);
+        let mut read = BytesMut::new();
+        while let Some(frame) = rest.next().await {
+            let data = match frame.map(Frame::into_data) {
+                Ok(Ok(data)) => data,
+                // Trailers are dropped, like `HttpBody::collect` does
+                Ok(Err(_)) => continue,
+                Err(e) => {
+                    // The stream is broken, keep failing the same way when it's sent
+                    let again: BoxError = e.to_string().into();
+                    self.unread(read.freeze(), stream::once(future::ready(Err(again))));
+                    return Err(ResponseBodyError::Collect(e));
+                }
+            };
+            if read.len() + data.len() > limit {
+                let pending = stream::once(future::ready(Ok(Frame::data(data))));
+                self.unread(read.freeze(), pending.chain(rest));
+                return Err(ResponseBodyError::TooLarge);
+            }
+            read.extend_from_slice(&data);
+        }
+
+        let bytes = read.freeze();
+        *self.inner.body_mut() = HttpBody::full(bytes.clone());
+        Ok(bytes)
+    }
+
+    /// Puts bytes taken out of a streaming body back in front of the rest of it.
+    fn unread<S>(&mut self, read: Bytes, rest: S)
+    where
+        S: Stream<Item = Result<Frame<Bytes>, BoxError>> + Send + Sync + 'static,
+    {
+        let read = (!read.is_empty()).then(|| Ok(Frame::data(read)));
+        *self.inner.body_mut() = HttpBody::stream_frames(stream::iter(read).chain(rest));
+    }
+
     /// Send a *non-streaming* body.
     #[inline]
     pub fn send(&mut self, body: impl Into<Bytes>) {
@@ -246,13 +447,26 @@ impl Response {
         *self.inner.body_mut() = HttpBody::stream_frames(mapped);
     }
 
-    /// Send a server-sent events (SSE) stream.
+    /// Send a server-sent events (SSE) stream of [`Event`](crate::Event)s, or of
+    /// `Bytes` already in the event stream format.
     ///
-    /// Sets standard SSE headers and automatically closes the stream when
-    /// application shutdown is triggered.
-    pub fn sse<S, E>(&mut self, stream: S)
+    /// Sets standard SSE headers, sends keep-alive comments every 15 seconds, and
+    /// automatically closes the stream when application shutdown is triggered.
+    pub fn sse<S, T, E>(&mut self, stream: S)
     where
-        S: Stream<Item = Result<Bytes, E>> + Send + Sync + 'static,
+        S: Stream<Item = Result<T, E>> + Send + Sync + 'static,
+        T: Into<Bytes> + 'static,
+        E: Into<BoxError> + 'static,
+    {
+        self.sse_with(stream, SseOptions::default());
+    }
+
+    /// Like [`sse`](Self::sse), with a different keep-alive interval or a token
+    /// to end the stream early.
+    pub fn sse_with<S, T, E>(&mut self, stream: S, options: SseOptions)
+    where
+        S: Stream<Item = Result<T, E>> + Send + Sync + 'static,
+        T: Into<Bytes> + 'static,
         E: Into<BoxError> + 'static,
     {
         self.header([
@@ -262,10 +476,12 @@ impl Response {
         ]);
 
         let shutdown = self.app.shutdown_token().cancelled_owned();
+        let cancelled = options.cancellation.clone().unwrap_or_default();
+        let guard = options.cancellation.map(CancellationToken::drop_guard);
 
-        let stream = stream
-            .map(|result| result.map_err(Into::into))
-            .take_until(shutdown);
+        let stream = SseStream::new(stream, options.keep_alive, guard)
+            .take_until(shutdown)
+            .take_until(cancelled.cancelled_owned());
 
         *self.inner.body_mut() = HttpBody::stream(stream);
     }
@@ -371,6 +587,9 @@ impl Response {
     }
 
     /// Streams a file as the response body, setting `Content-Type` and `Content-Length` automatically.
+    ///
+    /// Always sends the whole file, even for `HEAD`, range and conditional requests.
+    #[deprecated(note = "use `Ctx::send_file`, which answers range and conditional requests")]
     pub async fn send_file(
         &mut self,
         path: impl AsRef<std::path::Path>,
@@ -383,10 +602,163 @@ impl Response {
         self.header(("Content-Type", mime.as_ref()));
         self.header(("Content-Length", meta.len().to_string()));
 
-        self.stream(tokio_util::io::ReaderStream::with_capacity(file, 64 * 1024));
+        self.stream(ReaderStream::with_capacity(file, FILE_CHUNK_SIZE));
 
         Ok(())
     }
+
+    /// Like [`send_file`](Self::send_file), but also sets `Last-Modified`, `ETag` and
+    /// `Accept-Ranges`, and answers the request's conditional and range headers.
+    ///
+    /// Conditional requests whose cached copy is still fresh get a `304 Not Modified`.
+    /// `GET` requests for parts of the file get a `206 Partial Content`, as
+    /// `multipart/byteranges` when several ranges were asked for, or a
+    /// `416 Range Not Satisfiable` when none of them are in the file. Both only apply
+    /// while the status is `200 OK`, so error pages are always sent whole.
+    ///
+    /// ```rust,ignore
+    /// .get("/video", async |c: &mut Ctx| {
+    ///     c.send_file("video.mp4").await.map_err(|_| StatusError::not_found())
+    /// })
+    /// ```
+    pub async fn send_file_ranged(
+        &mut self,
+        req: &Request,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<(), std::io::Error> {
+        let path = path.as_ref();
+        // The metadata of the opened file, so the headers describe what is streamed even
+        // if the path is replaced meanwhile. `HEAD` sends no body and only needs the path's.
+        let (file, meta) = if req.is_head() {
+            (None, tokio::fs::metadata(path).await?)
+        } else {
+            let file = tokio::fs::File::open(path).await?;
+            let meta = file.metadata().await?;
+            (Some(file), meta)
+        };
+        let len = meta.len();
+
+        let modified = meta.modified().ok();
+        // Changes whenever the file is written, without reading it
+        let etag = modified
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .map(|m| format!("\"{:x}-{len:x}\"", m.as_nanos()));
+
+        let mime = mime_guess::from_path(path).first_or_octet_stream();
+        self.header((header::CONTENT_TYPE, mime.as_ref()));
+        self.header((header::ACCEPT_RANGES, "bytes"));
+        if let Some(modified) = modified {
+            self.header((header::LAST_MODIFIED, httpdate::fmt_http_date(modified)));
+        }
+        if let Some(etag) = &etag {
+            self.header((header::ETAG, etag.as_str()));
+        }
+
+        let ok = self.inner.status() == StatusCode::OK;
+        let method = req.method();
+        if ok
+            && (method == Method::GET || method == Method::HEAD)
+            && req.is_fresh(etag.as_deref(), modified)
+        {
+            self.inner.headers_mut().remove(header::CONTENT_TYPE);
+            self.status(StatusCode::NOT_MODIFIED);
+            return Ok(());
+        }
+
+        let ranges = match req.header(header::RANGE) {
+            Some(range)
+                if ok && method == Method::GET && req.if_range(etag.as_deref(), modified) =>
+            {
+                range::parse(range, len)
+            }
+            _ => Ranges::Full,
+        };
+
+        match ranges {
+            Ranges::Full => {
+                self.header((header::CONTENT_LENGTH, len));
+                if let Some(file) = file {
+                    self.stream(ReaderStream::with_capacity(file, FILE_CHUNK_SIZE));
+                }
+            }
+            Ranges::Unsatisfiable => {
+                self.inner.headers_mut().remove(header::CONTENT_TYPE);
+                self.header((header::CONTENT_RANGE, format!("bytes */{len}")));
+                self.send_status(StatusCode::RANGE_NOT_SATISFIABLE);
+            }
+            Ranges::Satisfiable(ranges) if ranges.len() == 1 => {
+                let range = ranges[0].clone();
+                self.status(StatusCode::PARTIAL_CONTENT);
+                self.header((
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{len}", range.start, range.end - 1),
+                ));
+                self.header((header::CONTENT_LENGTH, range.end - range.start));
+                if let Some(file) = file {
+                    self.stream(file_range(file, range).await?);
+                }
+            }
+            Ranges::Satisfiable(ranges) => {
+                let boundary = format!("{:016x}", RandomState::new().build_hasher().finish());
+
+                let mut length = 0;
+                let parts: Vec<_> = ranges
+                    .into_iter()
+                    .map(|range| {
+                        let head = Bytes::from(format!(
+                            "\r\n--{boundary}\r\nContent-Type: {mime}\r\nContent-Range: bytes {}-{}/{len}\r\n\r\n",
+                            range.start,
+                            range.end - 1
+                        ));
+                        length += head.len() as u64 + (range.end - range.start);
+                        (head, range)
+                    })
+                    .collect();
+                let tail = Bytes::from(format!("\r\n--{boundary}--\r\n"));
+                length += tail.len() as u64;
+
+                self.status(StatusCode::PARTIAL_CONTENT);
+                self.header((
+                    header::CONTENT_TYPE,
+                    format!("multipart/byteranges; boundary={boundary}"),
+                ));
+                self.header((header::CONTENT_LENGTH, length));
+                if file.is_none() {
+                    return Ok(());
+                }
+
+                // Each part opens the file again when it's reached, so a slow client
+                // doesn't hold more than one file handle
+                let path = path.to_owned();
+                let body = stream::iter(parts)
+                    .flat_map(move |(head, range)| {
+                        let path = path.clone();
+                        let data = stream::once(async move {
+                            file_range(tokio::fs::File::open(path).await?, range).await
+                        })
+                        .try_flatten();
+                        stream::once(future::ready(Ok(head))).chain(data)
+                    })
+                    .chain(stream::once(future::ready(Ok(tail))));
+                self.stream(body);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+async fn file_range(
+    mut file: tokio::fs::File,
+    range: std::ops::Range<u64>,
+) -> std::io::Result<ReaderStream<tokio::io::Take<tokio::fs::File>>> {
+    file.seek(std::io::SeekFrom::Start(range.start)).await?;
+    Ok(ReaderStream::with_capacity(
+        file.take(range.end - range.start),
+        FILE_CHUNK_SIZE,
+    ))
 }
 
 impl fmt::Debug for Response {
@@ -398,6 +770,28 @@ impl fmt::Debug for Response {
     }
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum ResponseBodyError {
+    #[error("Response body is too large")]
+    TooLarge,
+
+    #[error("Failed to collect response body")]
+    Collect(#[source] BoxError),
+}
+
+impl From<ResponseBodyError> for StatusError {
+    fn from(e: ResponseBodyError) -> Self {
+        match e {
+            ResponseBodyError::TooLarge => {
+                StatusError::internal_server_error().brief("Response body is too large")
+            }
+            ResponseBodyError::Collect(_) => {
+                StatusError::internal_server_error().brief("Failed to read response body")
+            }
+        }
+    }
+}
+
 pub trait SetIntoHeaders {
     fn into_headers(self, map: &mut HeaderMap) -> Result<(), Error>;
 }
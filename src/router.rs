@@ -5,6 +5,8 @@ use std::{
 
 use http::Method;
 
+#[cfg(feature = "middleware-cors")]
+use crate::app::App;
 use crate::{
     ctx::Ctx,
     handler::Handler,
@@ -14,7 +16,79 @@ use crate::{
 
 pub type Handlers = HashMap<Method, Arc<[DynHandlerRun]>>;
 
-pub(crate) type MatchRouter = matchit::Router<Handlers>;
+pub(crate) type MatchRouter = matchit::Router<Route>;
+
+/// A path registered in the built router, with the handler chain of every method.
+pub(crate) struct Route {
+    pub(crate) handlers: Handlers,
+    info: HashMap<Method, Arc<RouteInfo>>,
+    /// For methods without a name or metadata, e.g. the automatic `OPTIONS`
+    default_info: Arc<RouteInfo>,
+}
+
+impl Route {
+    pub(crate) fn info(&self, method: &Method) -> Arc<RouteInfo> {
+        self.info
+            .get(method)
+            .unwrap_or(&self.default_info)
+            .clone()
+    }
+}
+
+/// The route that matched a request, see `c.req.route_info()`.
+#[derive(Clone, Debug)]
+pub struct RouteInfo {
+    pub(crate) pattern: Arc<str>,
+    name: Option<Arc<str>>,
+    metadata: http::Extensions,
+}
+
+impl RouteInfo {
+    /// A route without a name or metadata.
+    pub(crate) fn new(pattern: Arc<str>) -> Self {
+        RouteInfo {
+            pattern,
+            name: None,
+            metadata: http::Extensions::new(),
+        }
+    }
+
+    /// The path as registered, e.g. `/user/{id}`
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// The name set with [`Router::name`]
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Metadata of type `T` set with [`Router::meta`]
+    pub fn meta<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.metadata.get::<T>()
+    }
+}
+
+/// Name and metadata attached to a router with [`Router::name`] and [`Router::meta`].
+#[derive(Clone, Debug, Default)]
+struct RouteMeta {
+    name: Option<Arc<str>>,
+    metadata: http::Extensions,
+}
+
+impl RouteMeta {
+    /// `child` on top of `self`, the child's name and values win.
+    fn merge(&self, child: &RouteMeta) -> RouteMeta {
+        let mut merged = self.clone();
+        if child.name.is_some() {
+            merged.name = child.name.clone();
+        }
+        merged.metadata.extend(child.metadata.clone());
+        merged
+    }
+}
+
+type MetaMap = HashMap<(String, Method), RouteMeta>;
 
 #[derive(Clone)]
 pub(crate) enum RouterItem {
@@ -28,6 +102,7 @@ pub(crate) enum RouterItem {
 pub struct Router {
     path: String,
     items: Arc<Mutex<Vec<RouterItem>>>,
+    meta: RouteMeta,
 }
 
 pub struct WithState<S, F>(pub S, pub F);
@@ -73,7 +148,58 @@ impl Router {
         Self {
             path,
             items: Arc::default(),
+            meta: RouteMeta::default(),
+        }
+    }
+
+    /// Names the route or group added last, e.g. for URL generation or logging.
+    ///
+    /// ```rust
+    /// # use maw::prelude::*;
+    /// let router = Router::new()
+    ///     .get("/user/{id}", async |_: &mut Ctx| "user")
+    ///     .name("user.show");
+    /// ```
+    ///
+    /// Ignored with a warning if nothing was added yet.
+    #[inline(never)]
+    pub fn name(&self, name: impl Into<String>) -> Self {
+        let name: String = name.into();
+        self.with_last_child("name", |meta| meta.name = Some(name.into()))
+    }
+
+    /// Attaches a value to the route or group added last, readable by every handler of it
+    /// with `c.req.route_meta::<T>()`. Values on a group apply to all of its routes,
+    /// and a route's own value of the same type wins.
+    ///
+    /// ```rust
+    /// # use maw::prelude::*;
+    /// #[derive(Clone)]
+    /// struct Role(&'static str);
+    ///
+    /// let router = Router::new()
+    ///     .get("/admin", async |_: &mut Ctx| "admin")
+    ///     .meta(Role("admin"));
+    /// ```
+    ///
+    /// Ignored with a warning if nothing was added yet.
+    #[inline(never)]
+    pub fn meta<T: Clone + Send + Sync + 'static>(&self, value: T) -> Self {
+        self.with_last_child("meta", |meta| {
+            meta.metadata.insert(value);
+        })
+    }
+
+    fn with_last_child(&self, method: &str, f: impl FnOnce(&mut RouteMeta)) -> Self {
+        let mut items = self.items.lock().unwrap();
+        match items.last_mut() {
+            Some(RouterItem::Child(child)) => f(&mut child.meta),
+            _ => tracing::warn!(
+                "Router::{method} has to be called right after adding a route or group, ignoring"
+            ),
         }
+        drop(items);
+        self.clone()
     }
 
     #[inline(never)]
@@ -118,8 +244,30 @@ impl Router {
     pub(crate) fn build(&self) -> Result<MatchRouter, matchit::InsertError> {
         let mut match_router = matchit::Router::new();
 
-        for (path, handlers) in self.flatten_routers() {
-            match_router.insert(path, handlers)?;
+        let mut metas = MetaMap::default();
+        let mut out = BTreeMap::default();
+        Self::walk("", self, &[], &RouteMeta::default(), &mut out, &mut metas);
+
+        for (path, handlers) in out {
+            let pattern: Arc<str> = path.as_str().into();
+            let info = handlers
+                .keys()
+                .filter_map(|method| {
+                    let meta = metas.remove(&(path.clone(), method.clone()))?;
+                    let info = RouteInfo {
+                        pattern: pattern.clone(),
+                        name: meta.name,
+                        metadata: meta.metadata,
+                    };
+                    Some((method.clone(), Arc::new(info)))
+                })
+                .collect();
+            let route = Route {
+                handlers,
+                info,
+                default_info: Arc::new(RouteInfo::new(pattern)),
+            };
+            match_router.insert(path, route)?;
         }
 
         Ok(match_router)
@@ -128,7 +276,14 @@ impl Router {
     #[inline(never)]
     pub(crate) fn flatten_routers(&self) -> BTreeMap<String, Handlers> {
         let mut out = BTreeMap::default();
-        Self::walk("", self, &[], &mut out);
+        Self::walk(
+            "",
+            self,
+            &[],
+            &RouteMeta::default(),
+            &mut out,
+            &mut MetaMap::default(),
+        );
         out
     }
 
@@ -137,9 +292,12 @@ impl Router {
         base: &str,
         router: &Router,
         inherited_mw: &[DynHandlerRun],
+        inherited_meta: &RouteMeta,
         out: &mut BTreeMap<String, Handlers>,
+        metas: &mut MetaMap,
     ) {
         let path = join_paths(base, &router.path);
+        let meta = inherited_meta.merge(&router.meta);
 
         let mut method_handlers: HashMap<Method, Arc<[DynHandlerRun]>> = HashMap::default();
         let mut inherited_for_children = inherited_mw.to_vec(); // Only global middlewares for children
@@ -167,7 +325,7 @@ impl Router {
                     }
                 },
                 RouterItem::Child(child) => {
-                    Self::walk(&path, child, &inherited_for_children, out);
+                    Self::walk(&path, child, &inherited_for_children, &meta, out, metas);
                 }
             }
         }
@@ -187,6 +345,9 @@ impl Router {
                     );
                 }
             }
+            for method in method_handlers.keys() {
+                metas.insert((path.clone(), method.clone()), meta.clone());
+            }
             entry.extend(method_handlers);
         }
     }
@@ -203,10 +364,13 @@ impl Router {
     pub fn static_files<E: rust_embed::RustEmbed + Send + Sync + 'static>(
         &self,
         prefix: &'static str,
-        files: crate::static_files::StaticFiles<E>,
+        mut files: crate::static_files::StaticFiles<E>,
     ) -> Self {
         let prefix = prefix.trim_matches('/');
         let has_index = E::get(files.index).is_some();
+        if !prefix.is_empty() {
+            files.prefix = ["/", prefix].concat();
+        }
 
         let (root, catch_all) = if prefix.is_empty() {
             ("/".to_string(), "/{*_}".to_string())
@@ -221,6 +385,22 @@ impl Router {
         };
         files.add_handlers(&r, Method::GET, catch_all, 5)
     }
+
+    /// Serves files from a directory on disk under `prefix`.
+    #[cfg(feature = "serve_dir")]
+    #[inline(never)]
+    pub fn serve_dir(&self, prefix: &'static str, dir: crate::serve_dir::ServeDir) -> Self {
+        let prefix = prefix.trim_matches('/');
+
+        let (root, catch_all) = if prefix.is_empty() {
+            ("/".to_string(), "/{*_}".to_string())
+        } else {
+            (["/", prefix].concat(), ["/", prefix, "/{*_}"].concat())
+        };
+
+        let r = dir.clone().add_handlers(self, Method::GET, root, 5);
+        dir.add_handlers(&r, Method::GET, catch_all, 5)
+    }
 }
 
 impl std::fmt::Debug for Router {
@@ -230,6 +410,89 @@ impl std::fmt::Debug for Router {
     }
 }
 
+/// Answers `OPTIONS` with `204` and an `Allow` header on the paths that use a handler
+/// of type `H` and have no `OPTIONS` handler of their own. Called by middlewares like
+/// CORS from their `on_app_listen_mut`, once the router is built.
+#[cfg(feature = "middleware-cors")]
+#[inline(never)]
+pub(crate) fn add_auto_options<H: 'static>(app: &mut App) {
+    let wanted = std::any::TypeId::of::<H>();
+    for (path, handlers) in app.router.flatten_routers() {
+        let uses = handlers
+            .values()
+            .flat_map(|chain| chain.iter())
+            .any(|h| h.type_id() == wanted);
+        if !uses || handlers.contains_key(&Method::OPTIONS) || handlers.contains_key(&crate::ALL) {
+            continue;
+        }
+
+        let Some(mut route) = app.built_router.remove(path.as_str()) else {
+            continue;
+        };
+        let options = auto_options(&route.handlers);
+        route.handlers.insert(Method::OPTIONS, options);
+        if let Err(e) = app.built_router.insert(path, route) {
+            tracing::error!("failed to add the automatic OPTIONS handler: {e}");
+        }
+    }
+}
+
+/// A preflight request runs the middlewares of the method in its
+/// `Access-Control-Request-Method`, the same ones the actual request will go through.
+#[cfg(feature = "middleware-cors")]
+fn auto_options(handlers: &Handlers) -> Arc<[DynHandlerRun]> {
+    let mut methods: Vec<&str> = handlers.keys().map(Method::as_str).collect();
+    if handlers.contains_key(&Method::GET) && !handlers.contains_key(&Method::HEAD) {
+        methods.push(Method::HEAD.as_str());
+    }
+    methods.push(Method::OPTIONS.as_str());
+    methods.sort_unstable();
+    let allow: Arc<str> = methods.join(", ").into();
+
+    let respond: DynHandlerRun = Arc::new(HandlerWrapper::new(
+        WithState(allow, async |c: &mut Ctx, allow: Arc<str>| {
+            c.res
+                .header((http::header::ALLOW, &*allow))
+                .status(http::StatusCode::NO_CONTENT);
+        }),
+        HandlerType::Method(Method::OPTIONS),
+        1,
+    ));
+
+    // Every method's chain, with its handler replaced by `respond`
+    let mut chains: Handlers = handlers
+        .iter()
+        .map(|(method, chain)| {
+            let mut chain = chain[..chain.len().saturating_sub(1)].to_vec();
+            chain.push(respond.clone());
+            (method.clone(), Arc::from(chain.into_boxed_slice()))
+        })
+        .collect();
+    if let Some(get) = chains.get(&Method::GET).cloned() {
+        chains.entry(Method::HEAD).or_insert(get);
+    }
+    let plain: Arc<[DynHandlerRun]> = Arc::new([respond]);
+
+    let dispatch: DynHandlerRun = Arc::new(HandlerWrapper::new(
+        WithState(
+            (Arc::new(chains), plain),
+            async |c: &mut Ctx, (chains, plain): (Arc<Handlers>, Arc<[DynHandlerRun]>)| {
+                let chain = c
+                    .req
+                    .header(http::header::ACCESS_CONTROL_REQUEST_METHOD)
+                    .and_then(|m| Method::from_bytes(m.as_bytes()).ok())
+                    .and_then(|m| chains.get(&m).cloned());
+                c.handlers = chain.unwrap_or(plain);
+                c.index_handler = 0;
+                c.next().await;
+            },
+        ),
+        HandlerType::Method(Method::OPTIONS),
+        1,
+    ));
+    Arc::new([dispatch])
+}
+
 #[inline(never)]
 fn join_paths(parent: &str, child: &str) -> String {
     match (parent, child) {
@@ -0,0 +1,286 @@
+use std::{
+    ffi::OsStr,
+    fmt::Write as _,
+    io,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use http::StatusCode;
+
+use crate::{ctx::Ctx, handler::Handler, prelude::StatusError};
+
+/// What to do with requests for files and directories whose name starts with a `.`,
+/// like `.git` or `.env`, also when reached through a symlink.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DotFiles {
+    /// Respond as if they didn't exist, and leave them out of directory listings.
+    #[default]
+    Ignore,
+    /// Respond with `403 Forbidden`, and leave them out of directory listings.
+    Deny,
+    /// Serve them like any other file.
+    Allow,
+}
+
+/// Serves files from a directory on disk, mounted with `Router::serve_dir`.
+///
+/// Files are sent with `Ctx::send_file`, so they support ranges and conditional
+/// requests. Paths can't leave the directory, neither with `..` (encoded or not) nor
+/// through symlinks pointing outside of it.
+///
+/// ```rust
+/// use maw::{prelude::*, ServeDir};
+///
+/// let router = Router::new().serve_dir("/downloads", ServeDir::new("./public").list_directories(true));
+/// ```
+#[derive(Clone, Debug)]
+pub struct ServeDir {
+    root: Arc<PathBuf>,
+    index_files: Arc<[String]>,
+    dotfiles: DotFiles,
+    list_directories: bool,
+}
+
+impl ServeDir {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: Arc::new(root.into()),
+            index_files: Arc::new(["index.html".to_string()]),
+            dotfiles: DotFiles::default(),
+            list_directories: false,
+        }
+    }
+
+    /// Files tried in order when a directory is requested. Pass an empty list to never
+    /// serve index files.
+    ///
+    /// Default: `["index.html"]`
+    pub fn index_files<I, S>(mut self, files: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.index_files = files.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Default: [`DotFiles::Ignore`]
+    pub fn dotfiles(mut self, dotfiles: DotFiles) -> Self {
+        self.dotfiles = dotfiles;
+        self
+    }
+
+    /// Respond to directories without an index file with an HTML listing of their entries.
+    ///
+    /// Default: false
+    pub fn list_directories(mut self, list: bool) -> Self {
+        self.list_directories = list;
+        self
+    }
+
+    /// Maps the request path to a path under the root, rejecting anything that isn't
+    /// a plain file name.
+    fn resolve(&self, path: &str) -> Result<PathBuf, StatusError> {
+        let decoded = percent_decode(path).ok_or_else(StatusError::bad_request)?;
+        // `\` is a separator on Windows, and NUL truncates paths in the OS
+        if decoded.contains(['\\', '\0']) {
+            return Err(StatusError::bad_request());
+        }
+
+        let mut resolved = PathBuf::from(&*self.root);
+        for segment in decoded.split('/').filter(|s| !s.is_empty() && *s != ".") {
+            let mut components = Path::new(segment).components();
+            let (Some(Component::Normal(name)), None) = (components.next(), components.next())
+            else {
+                return Err(StatusError::not_found());
+            };
+            self.check_dotfile(name)?;
+            resolved.push(name);
+        }
+        Ok(resolved)
+    }
+
+    /// Applies the dotfiles policy to every component of `path` below `root`, both canonical.
+    /// Symlinks with plain names can still lead to dotfiles, like `config -> .env`.
+    fn check_canonical(&self, root: &Path, path: &Path) -> Result<(), StatusError> {
+        let relative = path.strip_prefix(root).map_err(|_| {
+            tracing::debug!(path = %path.display(), "ServeDir rejected a path outside of its root");
+            StatusError::not_found()
+        })?;
+        for component in relative.components() {
+            if let Component::Normal(name) = component {
+                self.check_dotfile(name)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_dotfile(&self, name: &OsStr) -> Result<(), StatusError> {
+        if !name.as_encoded_bytes().starts_with(b".") {
+            return Ok(());
+        }
+        match self.dotfiles {
+            DotFiles::Ignore => Err(StatusError::not_found()),
+            DotFiles::Deny => Err(StatusError::forbidden()),
+            DotFiles::Allow => Ok(()),
+        }
+    }
+
+    /// `is_root` leaves out the link to the parent, which isn't served by us.
+    async fn list(&self, dir: &Path, url_path: &str, is_root: bool) -> io::Result<String> {
+        let mut entries = Vec::new();
+        let mut read_dir = tokio::fs::read_dir(dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            if name.starts_with('.') && self.dotfiles != DotFiles::Allow {
+                continue;
+            }
+            // Follows symlinks, unlike `entry.file_type()`
+            let is_dir = tokio::fs::metadata(entry.path())
+                .await
+                .is_ok_and(|m| m.is_dir());
+            entries.push((!is_dir, name));
+        }
+        // Directories first
+        entries.sort();
+
+        let decoded = percent_decode(url_path);
+        let title = escape_html(&format!(
+            "Index of {}",
+            decoded.as_deref().unwrap_or(url_path)
+        ));
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{title}</title></head>\n<body>\n<h1>{title}</h1>\n<ul>\n"
+        );
+        if !is_root {
+            html.push_str("<li><a href=\"../\">../</a></li>\n");
+        }
+        for (is_file, name) in entries {
+            let slash = if is_file { "" } else { "/" };
+            let _ = writeln!(
+                html,
+                "<li><a href=\"{}{slash}\">{}{slash}</a></li>",
+                percent_encode(&name),
+                escape_html(&name)
+            );
+        }
+        html.push_str("</ul>\n</body>\n</html>\n");
+        Ok(html)
+    }
+}
+
+impl Handler<&mut Ctx> for ServeDir {
+    type Output = Result<(), StatusError>;
+
+    async fn call(&self, c: &mut Ctx) -> Self::Output {
+        let path = self.resolve(c.req.param_str("_"))?;
+
+        // Symlinks may point anywhere, so compare where the path really leads
+        let (Ok(root), Ok(path)) = (
+            tokio::fs::canonicalize(&*self.root).await,
+            tokio::fs::canonicalize(&path).await,
+        ) else {
+            return Err(StatusError::not_found());
+        };
+        self.check_canonical(&root, &path)?;
+
+        let is_dir = tokio::fs::metadata(&path)
+            .await
+            .map_err(|_| StatusError::not_found())?
+            .is_dir();
+        if !is_dir {
+            return send_file(c, &path).await;
+        }
+
+        // Relative links in the index or listing need the trailing slash
+        let url_path = c.req.uri().path();
+        if !url_path.ends_with('/') {
+            let location = match c.req.uri().query() {
+                Some(query) => format!("{url_path}/?{query}"),
+                None => format!("{url_path}/"),
+            };
+            c.res
+                .redirect(location, Some(StatusCode::MOVED_PERMANENTLY));
+            return Ok(());
+        }
+
+        for index in self.index_files.iter() {
+            let Ok(index) = tokio::fs::canonicalize(path.join(index)).await else {
+                continue;
+            };
+            if self.check_canonical(&root, &index).is_ok()
+                && tokio::fs::metadata(&index).await.is_ok_and(|m| m.is_file())
+            {
+                return send_file(c, &index).await;
+            }
+        }
+
+        if !self.list_directories {
+            return Err(StatusError::not_found());
+        }
+        let url_path = url_path.to_string();
+        let html = self
+            .list(&path, &url_path, path == root)
+            .await
+            .map_err(|_| StatusError::internal_server_error())?;
+        c.res.header(("Content-Type", "text/html; charset=utf-8"));
+        c.res.send(html);
+        Ok(())
+    }
+}
+
+async fn send_file(c: &mut Ctx, path: &Path) -> Result<(), StatusError> {
+    c.send_file(path).await.map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => StatusError::not_found(),
+        io::ErrorKind::PermissionDenied => StatusError::forbidden(),
+        _ => StatusError::internal_server_error(),
+    })
+}
+
+/// Decodes `%XX` escapes, `None` if they're malformed or don't decode to UTF-8.
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            out.push(b as char);
+        } else {
+            let _ = write!(out, "%{b:02X}");
+        }
+    }
+    out
+}
+
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(ch),
+        }
+    }
+    out
+}
@@ -0,0 +1,211 @@
+use std::{
+    future::Future,
+    net::{Ipv4Addr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+};
+
+use bytes::Bytes;
+
+use crate::{
+    app::{App, handle_request},
+    response::{BoxError, HttpBody},
+};
+
+type HttpResponse = http::Response<HttpBody>;
+
+/// A maw [`App`] turned into a service, see [`App::into_service`].
+///
+/// Accepts requests with any body, like hyper's `Incoming` or another service's body.
+/// The peer address passed to `c.req.ip()` is read from a [`SocketAddr`] in the
+/// request extensions, if your stack puts one there.
+#[derive(Clone)]
+pub struct AppService {
+    pub(crate) app: Arc<App>,
+}
+
+impl AppService {
+    pub fn app(&self) -> &App {
+        &self.app
+    }
+
+    fn serve<B>(
+        &self,
+        req: http::Request<B>,
+    ) -> Pin<Box<dyn Future<Output = Result<HttpResponse, BoxError>> + Send + 'static>>
+    where
+        B: http_body::Body<Data = Bytes> + Send + 'static,
+        B::Error: Into<BoxError>,
+    {
+        let app = self.app.clone();
+        let peer_addr = req
+            .extensions()
+            .get::<SocketAddr>()
+            .copied()
+            .unwrap_or_else(|| SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)));
+        Box::pin(async move {
+            handle_request(req.map(HttpBody::from_body), app, peer_addr)
+                .await
+                .map_err(Into::into)
+        })
+    }
+}
+
+impl<B> hyper::service::Service<http::Request<B>> for AppService
+where
+    B: http_body::Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    type Response = HttpResponse;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<HttpResponse, BoxError>> + Send + 'static>>;
+
+    fn call(&self, req: http::Request<B>) -> Self::Future {
+        self.serve(req)
+    }
+}
+
+#[cfg(feature = "tower")]
+impl<B> tower_service::Service<http::Request<B>> for AppService
+where
+    B: http_body::Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    type Response = HttpResponse;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<HttpResponse, BoxError>> + Send + 'static>>;
+
+    fn poll_ready(
+        &mut self,
+        _: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        self.serve(req)
+    }
+}
+
+#[cfg(feature = "tower")]
+mod mount {
+    use bytes::Bytes;
+    use http::Uri;
+
+    use crate::{
+        ctx::Ctx,
+        prelude::StatusError,
+        response::{BoxError, HttpBody},
+        router::{AddHandlers as _, Router, WithState},
+    };
+
+    impl Router {
+        /// Mounts a `tower::Service` under `prefix`, for every method.
+        ///
+        /// The request is forwarded with `prefix` stripped from its path and the
+        /// body as an [`HttpBody`]. The response body is mapped back into an [`HttpBody`].
+        ///
+        /// Middlewares registered before the mount run as usual, headers they set are kept
+        /// unless the service's response has the same header.
+        pub fn mount_service<S, B>(&self, prefix: &'static str, service: S) -> Self
+        where
+            S: tower_service::Service<http::Request<HttpBody>, Response = http::Response<B>>
+                + Clone
+                + Send
+                + Sync
+                + 'static,
+            S::Error: Into<BoxError>,
+            S::Future: Send,
+            B: http_body::Body<Data = Bytes> + Send + 'static,
+            B::Error: Into<BoxError>,
+        {
+            let prefix = prefix.trim_matches('/');
+            let (root, catch_all) = if prefix.is_empty() {
+                ("/".to_string(), "/{*_}".to_string())
+            } else {
+                (["/", prefix].concat(), ["/", prefix, "/{*_}"].concat())
+            };
+
+            let r = WithState(service.clone(), call_service::<S, B>).add_handlers(
+                self,
+                crate::all(),
+                root,
+                5,
+            );
+            WithState(service, call_service::<S, B>).add_handlers(&r, crate::all(), catch_all, 5)
+        }
+    }
+
+    async fn call_service<S, B>(c: &mut Ctx, mut service: S) -> Result<(), StatusError>
+    where
+        S: tower_service::Service<http::Request<HttpBody>, Response = http::Response<B>>,
+        S::Error: Into<BoxError>,
+        B: http_body::Body<Data = Bytes> + Send + 'static,
+        B::Error: Into<BoxError>,
+    {
+        let rest = c.req.param_str("_");
+        let path_and_query = match c.req.uri().query() {
+            Some(q) => ["/", rest, "?", q].concat(),
+            None => ["/", rest].concat(),
+        };
+
+        let mut uri = c.req.uri().clone().into_parts();
+        uri.path_and_query = Some(path_and_query.parse().map_err(|e| {
+            StatusError::bad_request()
+                .brief("Invalid request path")
+                .error(e)
+        })?);
+        let uri = Uri::from_parts(uri).map_err(|e| {
+            StatusError::bad_request()
+                .brief("Invalid request path")
+                .error(e)
+        })?;
+
+        let body = match c.req.take_body() {
+            Some(body) => body,
+            None => match c.req.cached_body.clone() {
+                Some(bytes) => HttpBody::full(bytes),
+                None => HttpBody::Empty,
+            },
+        };
+
+        let mut req = http::Request::new(body);
+        *req.method_mut() = c.req.method().clone();
+        *req.uri_mut() = uri;
+        *req.version_mut() = c.req.version();
+        *req.headers_mut() = c.req.headers().clone();
+        *req.extensions_mut() = c.req.parts.extensions.clone();
+
+        futures_util::future::poll_fn(|cx| service.poll_ready(cx))
+            .await
+            .map_err(|e| StatusError::service_unavailable().error(e))?;
+        let res = service
+            .call(req)
+            .await
+            .map_err(|e| StatusError::internal_server_error().error(e))?;
+
+        // Keep the headers set before the mount, the service's own win
+        let (parts, body) = res.into_parts();
+        let headers = c.res.inner.headers_mut();
+        let mut current = None;
+        for (name, value) in parts.headers {
+            match name {
+                Some(name) => {
+                    headers.insert(&name, value);
+                    current = Some(name);
+                }
+                // Further values of the same header
+                None => {
+                    if let Some(name) = &current {
+                        headers.append(name, value);
+                    }
+                }
+            }
+        }
+        c.res.inner.extensions_mut().extend(parts.extensions);
+        *c.res.inner.body_mut() = HttpBody::from_body(body);
+        c.res.status(parts.status);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,285 @@
+//! Server-sent events, sent with `Response::sse`.
+//!
+//! ```rust,ignore
+//! .get("/events", async |c: &mut Ctx| {
+//!     let from = c.req.last_event_id().and_then(|id| id.parse().ok()).unwrap_or(0);
+//!     let stream = futures_util::stream::iter(from..).map(|n| {
+//!         Event::new().id(n.to_string()).event("tick").json_data(&n)
+//!     });
+//!     c.res.sse(stream);
+//! })
+//! ```
+
+use std::{
+    fmt::Write as _,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use futures_util::Stream;
+use serde::Serialize;
+use tokio::time::{Instant, Sleep};
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+use crate::{request::Request, response::BoxError};
+
+const KEEP_ALIVE: Bytes = Bytes::from_static(b":\n\n");
+
+/// A single event of an SSE stream. Streams passed to `Response::sse` can yield these,
+/// or `Bytes` already in the event stream format.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    retry: Option<Duration>,
+    data: Option<String>,
+    comment: Option<String>,
+}
+
+impl Event {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sent back by the browser as `Last-Event-ID` when it reconnects, see
+    /// `Request::last_event_id`.
+    ///
+    /// Newlines and NUL are removed, they would end the field or make browsers ignore it.
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        let mut id = id.into();
+        id.retain(|c| !matches!(c, '\n' | '\r' | '\0'));
+        self.id = Some(id);
+        self
+    }
+
+    /// The event type, which browsers dispatch to `addEventListener(name)` instead of
+    /// `onmessage`.
+    ///
+    /// Newlines are removed, they would end the field.
+    pub fn event(mut self, event: impl Into<String>) -> Self {
+        let mut event = event.into();
+        event.retain(|c| !matches!(c, '\n' | '\r'));
+        self.event = Some(event);
+        self
+    }
+
+    /// How long the browser waits before reconnecting after the stream ends.
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// The event's data, may span several lines.
+    pub fn data(mut self, data: impl Into<String>) -> Self {
+        self.data = Some(data.into());
+        self
+    }
+
+    /// Serializes `value` as JSON for the event's data.
+    pub fn json_data<T: Serialize + ?Sized>(self, value: &T) -> Result<Self, serde_json::Error> {
+        Ok(self.data(serde_json::to_string(value)?))
+    }
+
+    /// A comment, ignored by browsers.
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+}
+
+/// Splits on `\n`, `\r\n` and `\r`, the line endings of the event stream format.
+fn lines(s: &str) -> impl Iterator<Item = &str> {
+    s.split('\n')
+        .flat_map(|line| line.strip_suffix('\r').unwrap_or(line).split('\r'))
+}
+
+impl From<Event> for Bytes {
+    fn from(event: Event) -> Self {
+        let mut buf = String::new();
+        if let Some(comment) = &event.comment {
+            for line in lines(comment) {
+                let _ = writeln!(buf, ": {line}");
+            }
+        }
+        if let Some(event_type) = &event.event {
+            let _ = writeln!(buf, "event: {event_type}");
+        }
+        if let Some(id) = &event.id {
+            let _ = writeln!(buf, "id: {id}");
+        }
+        if let Some(retry) = event.retry {
+            let _ = writeln!(buf, "retry: {}", retry.as_millis());
+        }
+        if let Some(data) = &event.data {
+            for line in lines(data) {
+                let _ = writeln!(buf, "data: {line}");
+            }
+        }
+        buf.push('\n');
+        Bytes::from(buf)
+    }
+}
+
+/// Options for `Response::sse_with`.
+#[derive(Clone, Debug)]
+pub struct SseOptions {
+    pub(crate) keep_alive: Option<Duration>,
+    pub(crate) cancellation: Option<CancellationToken>,
+}
+
+impl Default for SseOptions {
+    fn default() -> Self {
+        Self {
+            keep_alive: Some(Duration::from_secs(15)),
+            cancellation: None,
+        }
+    }
+}
+
+impl SseOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends a comment when no event was sent for this long, so proxies don't close
+    /// the idle connection. `None` disables it.
+    ///
+    /// Default: 15 seconds
+    pub fn keep_alive(mut self, interval: Option<Duration>) -> Self {
+        self.keep_alive = interval;
+        self
+    }
+
+    /// Ends the stream when `token` is cancelled, and cancels `token` when the stream
+    /// ends, including when the client disconnects. Lets tasks feeding the stream stop.
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+}
+
+/// Adds keep-alive comments to an event stream, and cancels the token when dropped.
+pub(crate) struct SseStream<S> {
+    inner: Pin<Box<S>>,
+    keep_alive: Option<(Duration, Pin<Box<Sleep>>)>,
+    _guard: Option<DropGuard>,
+}
+
+impl<S> SseStream<S> {
+    pub(crate) fn new(inner: S, keep_alive: Option<Duration>, guard: Option<DropGuard>) -> Self {
+        Self {
+            inner: Box::pin(inner),
+            keep_alive: keep_alive.map(|d| (d, Box::pin(tokio::time::sleep(d)))),
+            _guard: guard,
+        }
+    }
+}
+
+impl<S, T, E> Stream for SseStream<S>
+where
+    S: Stream<Item = Result<T, E>>,
+    T: Into<Bytes>,
+    E: Into<BoxError>,
+{
+    type Item = Result<Bytes, BoxError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.inner.as_mut().poll_next(cx) {
+            Poll::Ready(item) => {
+                if let Some((interval, sleep)) = &mut self.keep_alive {
+                    sleep.as_mut().reset(Instant::now() + *interval);
+                }
+                return Poll::Ready(item.map(|r| r.map(Into::into).map_err(Into::into)));
+            }
+            Poll::Pending => {}
+        }
+
+        if let Some((interval, sleep)) = &mut self.keep_alive
+            && sleep.as_mut().poll(cx).is_ready()
+        {
+            sleep.as_mut().reset(Instant::now() + *interval);
+            return Poll::Ready(Some(Ok(KEEP_ALIVE)));
+        }
+        Poll::Pending
+    }
+}
+
+impl Request {
+    /// The `Last-Event-ID` header, sent by browsers reconnecting to an SSE stream with
+    /// the ID of the last event they received.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.header("Last-Event-ID")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(event: Event) -> String {
+        String::from_utf8(Bytes::from(event).to_vec()).unwrap()
+    }
+
+    #[test]
+    fn data_only() {
+        assert_eq!(encode(Event::new().data("hello")), "data: hello\n\n");
+        assert_eq!(encode(Event::new().data("")), "data: \n\n");
+    }
+
+    #[test]
+    fn multi_line_data() {
+        assert_eq!(
+            encode(Event::new().data("one\ntwo\n\nfour")),
+            "data: one\ndata: two\ndata: \ndata: four\n\n"
+        );
+        // A trailing newline is kept as an empty last line
+        assert_eq!(encode(Event::new().data("one\n")), "data: one\ndata: \n\n");
+    }
+
+    #[test]
+    fn cr_and_crlf() {
+        assert_eq!(
+            encode(Event::new().data("one\r\ntwo\rthree\r\r\nfour")),
+            "data: one\ndata: two\ndata: three\ndata: \ndata: four\n\n"
+        );
+        assert_eq!(
+            encode(Event::new().comment("a\rb\r\nc")),
+            ": a\n: b\n: c\n\n"
+        );
+    }
+
+    #[test]
+    fn field_order() {
+        let event = Event::new()
+            .data("{}")
+            .retry(Duration::from_millis(1500))
+            .id("7")
+            .event("update")
+            .comment("note");
+        assert_eq!(
+            encode(event),
+            ": note\nevent: update\nid: 7\nretry: 1500\ndata: {}\n\n"
+        );
+    }
+
+    #[test]
+    fn comment_only() {
+        assert_eq!(encode(Event::new().comment("ping")), ": ping\n\n");
+        assert_eq!(encode(Event::new().comment("")), ": \n\n");
+        assert_eq!(encode(Event::new()), "\n");
+    }
+
+    #[test]
+    fn id_and_event_stay_on_one_line() {
+        let event = Event::new().id("a\r\nb\0c").event("x\ny\rz").data("d");
+        assert_eq!(encode(event), "event: xyz\nid: abc\ndata: d\n\n");
+    }
+
+    #[test]
+    fn json_data() {
+        let event = Event::new().json_data(&["a\nb"]).unwrap();
+        assert_eq!(encode(event), "data: [\"a\\nb\"]\n\n");
+    }
+}
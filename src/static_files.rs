@@ -1,19 +1,34 @@
 use std::{
+    fmt::Write as _,
     marker::PhantomData,
+    sync::Arc,
     time::{Duration, UNIX_EPOCH},
 };
 
-use http::StatusCode;
-use httpdate::{fmt_http_date, parse_http_date};
-use rust_embed::RustEmbed;
+use http::{StatusCode, header};
+use httpdate::fmt_http_date;
+use rust_embed::{EmbeddedFile, RustEmbed};
 
-use crate::{ctx::Ctx, handler::Handler};
+use crate::{ctx::Ctx, handler::Handler, negotiate};
+
+/// Content codings of precompressed variants, by their file extension, in order of preference.
+const PRECOMPRESSED: [(&str, &str); 3] = [("br", "br"), ("zstd", "zst"), ("gzip", "gz")];
+
+/// Hex digits of the content hash put in fingerprinted file names.
+const FINGERPRINT_LEN: usize = 16;
+
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
 
 pub struct StaticFiles<E> {
     _marker: PhantomData<E>,
     pub(crate) index: &'static str,
+    pub(crate) prefix: String,
     cache_control: Option<String>,
+    cache_policies: Arc<[(String, String)]>,
     fallback_to: Option<&'static str>,
+    precompressed: bool,
+    #[cfg(feature = "minijinja")]
+    template_function: Option<&'static str>,
 }
 
 impl<E: RustEmbed> StaticFiles<E> {
@@ -21,8 +36,13 @@ impl<E: RustEmbed> StaticFiles<E> {
         Self {
             _marker: PhantomData,
             index: "index.html",
+            prefix: String::new(),
             cache_control: None,
+            cache_policies: Arc::new([]),
             fallback_to: None,
+            precompressed: true,
+            #[cfg(feature = "minijinja")]
+            template_function: None,
         }
     }
 
@@ -40,6 +60,27 @@ impl<E: RustEmbed> StaticFiles<E> {
         self
     }
 
+    /// Sends `value` as `Cache-Control` for files whose path matches `pattern`, instead
+    /// of `max_age`. Patterns are checked in the order they were added.
+    ///
+    /// Patterns match the path of the served file, without the mount prefix. `*` matches anything
+    /// but `/`, `**` matches anything, `**/` zero or more directories, `?` one character, and
+    /// `[hash]` the hash of bundler fingerprints, 8 or more of `[A-Za-z0-9_-]`.
+    ///
+    /// Fingerprinted URLs from [`StaticFiles::fingerprinted`] are always `immutable`.
+    ///
+    /// ```rust,ignore
+    /// StaticFiles::new(Assets)
+    ///     .cache_control("**/*.[hash].js", "public, max-age=31536000, immutable")
+    ///     .cache_control("index.html", "no-cache")
+    /// ```
+    pub fn cache_control(mut self, pattern: impl Into<String>, value: impl Into<String>) -> Self {
+        let mut policies = self.cache_policies.to_vec();
+        policies.push((pattern.into(), value.into()));
+        self.cache_policies = policies.into();
+        self
+    }
+
     /// Serve a specific embedded file when no matching path is found,
     /// going through the same last-modified/cache-control logic as normal files.
     ///
@@ -51,6 +92,124 @@ impl<E: RustEmbed> StaticFiles<E> {
         self.fallback_to = Some(file);
         self
     }
+
+    /// Serve `app.js.br`, `app.js.zst` or `app.js.gz` instead of `app.js` when they are
+    /// embedded and the client accepts the encoding, with `app.js`'s content type.
+    ///
+    /// Default: true
+    pub fn precompressed(mut self, enabled: bool) -> Self {
+        self.precompressed = enabled;
+        self
+    }
+
+    /// Registers a template function with this name, returning the fingerprinted URL of an
+    /// embedded file, with the prefix passed to `Router::static_files`. Every mount registers
+    /// its own, so the same files mounted twice need two names.
+    ///
+    /// ```jinja
+    /// <script src="{{ asset('js/app.js') }}"></script>
+    /// {# <script src="/static/js/app.3f2a9c1b5e6d7a8b.js"></script> #}
+    /// ```
+    #[cfg(feature = "minijinja")]
+    pub fn template_function(mut self, name: &'static str) -> Self {
+        self.template_function = Some(name);
+        self
+    }
+
+    /// The path of an embedded file with its content hash in the name, like
+    /// `js/app.3f2a9c1b5e6d7a8b.js` for `js/app.js`, or `None` if it isn't embedded.
+    ///
+    /// Fingerprinted paths are served as the original file, and can be cached forever since
+    /// a new build changes the path.
+    pub fn fingerprinted(path: &str) -> Option<String> {
+        let path = path.trim_start_matches('/');
+        let hash = fingerprint(&E::get(path)?);
+        let (dir, name) = match path.rsplit_once('/') {
+            Some((dir, name)) => (&path[..dir.len() + 1], name),
+            None => ("", path),
+        };
+        Some(match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => format!("{dir}{stem}.{hash}.{ext}"),
+            _ => format!("{dir}{name}.{hash}"),
+        })
+    }
+
+    /// The original file of a fingerprinted path, if the hash is still the current one.
+    fn unfingerprint(path: &str) -> Option<(EmbeddedFile, String)> {
+        let (dir, name) = match path.rsplit_once('/') {
+            Some((dir, name)) => (&path[..dir.len() + 1], name),
+            None => ("", path),
+        };
+        let is_hash =
+            |s: &str| s.len() == FINGERPRINT_LEN && s.bytes().all(|b| b.is_ascii_hexdigit());
+
+        let (original, hash) = match name.rsplit_once('.') {
+            Some((stem, hash)) if is_hash(hash) => ([dir, stem].concat(), hash),
+            Some((rest, ext)) => match rest.rsplit_once('.') {
+                Some((stem, hash)) if is_hash(hash) => (format!("{dir}{stem}.{ext}"), hash),
+                _ => return None,
+            },
+            None => return None,
+        };
+        let file = E::get(&original)?;
+        (fingerprint(&file) == hash).then_some((file, original))
+    }
+
+    /// The embedded file for a request path, the path it was found at, and whether
+    /// the request was for a fingerprinted path.
+    fn lookup(&self, path: &str) -> Option<(EmbeddedFile, String, bool)> {
+        if path.is_empty() || path.ends_with('/') {
+            let full = [path, self.index].concat();
+            return E::get(&full).map(|f| (f, full, false));
+        }
+        if let Some(f) = E::get(path) {
+            return Some((f, path.to_string(), false));
+        }
+        if let Some((f, original)) = Self::unfingerprint(path) {
+            return Some((f, original, true));
+        }
+        let full = [path, "/", self.index].concat();
+        E::get(&full).map(|f| (f, full, false))
+    }
+
+    fn cache_control_for(&self, path: &str, fingerprinted: bool) -> Option<&str> {
+        if fingerprinted {
+            return Some(IMMUTABLE);
+        }
+        self.cache_policies
+            .iter()
+            .find(|(pattern, _)| glob(pattern.as_bytes(), path.as_bytes()))
+            .map(|(_, value)| value.as_str())
+            .or(self.cache_control.as_deref())
+    }
+
+    /// The best precompressed variant of `path` the client accepts, with its coding.
+    fn precompressed_variant(
+        &self,
+        c: &mut Ctx,
+        path: &str,
+    ) -> Option<(EmbeddedFile, &'static str)> {
+        if !self.precompressed {
+            return None;
+        }
+        let variants: Vec<_> = PRECOMPRESSED
+            .iter()
+            .filter_map(|&(coding, ext)| E::get(&[path, ".", ext].concat()).map(|f| (f, coding)))
+            .collect();
+        if variants.is_empty() {
+            return None;
+        }
+
+        // Whether a variant is sent or not, the response depends on the header
+        c.res.vary("Accept-Encoding");
+        let accept_encoding = c.req.header(header::ACCEPT_ENCODING)?;
+        negotiate::encoding(
+            accept_encoding,
+            variants
+                .into_iter()
+                .map(|(file, coding)| ((file, coding), coding)),
+        )
+    }
 }
 
 impl<E> Clone for StaticFiles<E> {
@@ -58,35 +217,53 @@ impl<E> Clone for StaticFiles<E> {
         Self {
             _marker: PhantomData,
             index: self.index,
+            prefix: self.prefix.clone(),
             cache_control: self.cache_control.clone(),
+            cache_policies: self.cache_policies.clone(),
             fallback_to: self.fallback_to,
+            precompressed: self.precompressed,
+            #[cfg(feature = "minijinja")]
+            template_function: self.template_function,
         }
     }
 }
 
-impl<E: RustEmbed + Sync> Handler<&mut Ctx> for StaticFiles<E> {
+impl<E: RustEmbed + Sync + 'static> Handler<&mut Ctx> for StaticFiles<E> {
     type Output = ();
 
+    #[cfg(feature = "minijinja")]
+    fn on_app_listen_mut(&self, app: &mut crate::prelude::App) {
+        let Some(name) = self.template_function else {
+            return;
+        };
+        let prefix = self.prefix.clone();
+        app.jinja.with(|env| {
+            env.add_function(name, move |path: &str| {
+                Self::fingerprinted(path)
+                    .map(|fingerprinted| [prefix.as_str(), "/", &fingerprinted].concat())
+                    .ok_or_else(|| {
+                        minijinja::Error::new(
+                            minijinja::ErrorKind::InvalidOperation,
+                            format!("no embedded file at {path:?}"),
+                        )
+                    })
+            });
+        });
+    }
+
+    /// Every mount registers its own template function, with its own prefix
+    #[cfg(feature = "minijinja")]
+    fn on_app_listen_key(&self) -> Option<String> {
+        Some(self.prefix.clone())
+    }
+
     async fn call(&self, c: &mut Ctx) -> Self::Output {
         let path = c.req.param_str("_");
 
-        let (file, mime_path) = if path.is_empty() || path.ends_with('/') {
-            let full = [path, self.index].concat();
-            (E::get(&full), full)
-        } else {
-            match E::get(path) {
-                Some(f) => (Some(f), path.to_string()),
-                None => {
-                    let full = [path, "/", self.index].concat();
-                    (E::get(&full), full)
-                }
-            }
-        };
-
-        let (file, mime_path) = match file {
-            Some(f) => (f, mime_path),
+        let (file, mime_path, fingerprinted) = match self.lookup(path) {
+            Some(found) => found,
             None => match self.fallback_to.and_then(|p| E::get(p).map(|f| (f, p))) {
-                Some((f, p)) => (f, p.to_string()),
+                Some((f, p)) => (f, p.to_string(), false),
                 None => {
                     c.res.send_status(StatusCode::NOT_FOUND);
                     return;
@@ -94,29 +271,141 @@ impl<E: RustEmbed + Sync> Handler<&mut Ctx> for StaticFiles<E> {
             },
         };
 
-        if let Some(last_modified) = file.metadata.last_modified() {
-            let modified = UNIX_EPOCH + Duration::from_secs(last_modified);
+        if let Some(cc) = self.cache_control_for(&mime_path, fingerprinted) {
+            c.res.header(("Cache-Control", cc));
+        }
+
+        let (file, coding) = match self.precompressed_variant(c, &mime_path) {
+            Some((variant, coding)) => (variant, Some(coding)),
+            None => (file, None),
+        };
+
+        // The hash of what is actually sent, so each encoding gets its own ETag
+        let etag = format!("\"{}\"", hex(&file.metadata.sha256_hash()[..16]));
+        c.res.header((header::ETAG, etag.as_str()));
+
+        let modified = file
+            .metadata
+            .last_modified()
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+        if let Some(modified) = modified {
             c.res.header(("Last-Modified", fmt_http_date(modified)));
+        }
 
-            if let Some(ims) = c.req.headers().get("If-Modified-Since") {
-                if let Ok(ims_str) = ims.to_str() {
-                    if let Ok(ims_time) = parse_http_date(ims_str) {
-                        if modified <= ims_time {
-                            c.res.send_status(StatusCode::NOT_MODIFIED);
-                            return;
-                        }
-                    }
-                }
-            }
+        if c.req.is_fresh(Some(&etag), modified) {
+            c.res.send_status(StatusCode::NOT_MODIFIED);
+            return;
+        }
+
+        if let Some(coding) = coding {
+            c.res.header((header::CONTENT_ENCODING, coding));
         }
 
         let mime = mime_guess::from_path(&mime_path).first_or_octet_stream();
         c.res.header(("Content-Type", mime.as_ref()));
 
-        if let Some(cc) = &self.cache_control {
-            c.res.header(("Cache-Control", cc.as_str()));
+        c.res.send(file.data.into_owned());
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .fold(String::with_capacity(bytes.len() * 2), |mut s, b| {
+            let _ = write!(s, "{b:02x}");
+            s
+        })
+}
+
+fn fingerprint(file: &EmbeddedFile) -> String {
+    hex(&file.metadata.sha256_hash()[..FINGERPRINT_LEN / 2])
+}
+
+/// Matches `path` against a `cache_control` pattern.
+fn glob(pattern: &[u8], path: &[u8]) -> bool {
+    const HASH: &[u8] = b"[hash]";
+    let is_hash_char = |b: &u8| b.is_ascii_alphanumeric() || *b == b'-' || *b == b'_';
+
+    match pattern {
+        [] => path.is_empty(),
+        // Zero or more whole directories, so `**/*.css` also matches `main.css`
+        [b'*', b'*', b'/', rest @ ..] => {
+            glob(rest, path)
+                || (1..=path.len())
+                    .filter(|&i| path[i - 1] == b'/')
+                    .any(|i| glob(rest, &path[i..]))
         }
+        [b'*', b'*', rest @ ..] => (0..=path.len()).any(|i| glob(rest, &path[i..])),
+        [b'*', rest @ ..] => (0..=path.len())
+            .take_while(|&i| i == 0 || path[i - 1] != b'/')
+            .any(|i| glob(rest, &path[i..])),
+        [b'?', rest @ ..] => path.first().is_some_and(|&b| b != b'/') && glob(rest, &path[1..]),
+        _ if pattern.starts_with(HASH) => {
+            let len = path.iter().take_while(|b| is_hash_char(b)).count();
+            (8..=len).any(|i| glob(&pattern[HASH.len()..], &path[i..]))
+        }
+        [b, rest @ ..] => path.first() == Some(b) && glob(rest, &path[1..]),
+    }
+}
 
-        c.res.send(file.data.into_owned());
+#[cfg(test)]
+mod tests {
+    use super::glob;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        glob(pattern.as_bytes(), path.as_bytes())
+    }
+
+    #[test]
+    fn literal() {
+        assert!(matches("index.html", "index.html"));
+        assert!(!matches("index.html", "docs/index.html"));
+        assert!(!matches("index.html", "index.htm"));
+    }
+
+    #[test]
+    fn star_stays_in_one_segment() {
+        assert!(matches("*.css", "main.css"));
+        assert!(matches("assets/*.css", "assets/main.css"));
+        assert!(!matches("*.css", "assets/main.css"));
+        assert!(!matches("assets/*.css", "assets/css/main.css"));
+    }
+
+    #[test]
+    fn leading_double_star_matches_zero_or_more_dirs() {
+        assert!(matches("**/*.css", "main.css"));
+        assert!(matches("**/*.css", "assets/main.css"));
+        assert!(matches("**/*.css", "a/b/c/main.css"));
+        assert!(!matches("**/*.css", "main.js"));
+        assert!(!matches("**/main.css", "notmain.css"));
+    }
+
+    #[test]
+    fn inner_double_star_matches_zero_or_more_dirs() {
+        assert!(matches("assets/**/*.js", "assets/app.js"));
+        assert!(matches("assets/**/*.js", "assets/js/vendor/app.js"));
+        assert!(!matches("assets/**/*.js", "other/app.js"));
+        assert!(!matches("assets/**/app.js", "assetsapp.js"));
+    }
+
+    #[test]
+    fn trailing_double_star_matches_anything() {
+        assert!(matches("assets/**", "assets/a/b.png"));
+        assert!(matches("**", "anything/at/all"));
+    }
+
+    #[test]
+    fn question_mark() {
+        assert!(matches("?.js", "a.js"));
+        assert!(!matches("?.js", "ab.js"));
+        assert!(!matches("a?b", "a/b"));
+    }
+
+    #[test]
+    fn hash() {
+        assert!(matches("**/*.[hash].js", "app.3f2a9c1d.js"));
+        assert!(matches("**/*.[hash].js", "js/app.3f2a-9c_1dAB.js"));
+        assert!(!matches("**/*.[hash].js", "app.short.js"));
+        assert!(!matches("**/*.[hash].js", "app.js"));
     }
 }
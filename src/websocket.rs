@@ -118,6 +118,13 @@ impl Ctx {
             return Err(WsUpgradeError::NotWebSocket);
         }
 
+        #[cfg(feature = "middleware-metrics")]
+        let gauge = self
+            .req
+            .parts
+            .extensions
+            .remove::<crate::middlewares::metrics::WebSocketGauge>();
+
         let mut request = http::Request::new(());
         *request.headers_mut() = self.req.parts.headers.clone();
         *request.extensions_mut() = std::mem::take(&mut self.req.parts.extensions);
@@ -128,7 +135,11 @@ impl Ctx {
 
         tokio::spawn(async move {
             match WebSocket::from_hyper(ws_future).await {
-                Ok(ws) => handler(ws).await,
+                Ok(ws) => {
+                    #[cfg(feature = "middleware-metrics")]
+                    let _guard = gauge.as_ref().map(|g| g.enter());
+                    handler(ws).await
+                }
                 Err(e) => tracing::error!("WebSocket connection failed: {e}"),
             }
         });